@@ -0,0 +1,212 @@
+use std::fmt;
+use rand::Rng;
+use crate::error::{Error, ParseError};
+use crate::breakdown::Breakdown;
+use crate::expression::Expression;
+use crate::limits::Limits;
+use crate::tree::split_comment;
+use crate::system::{DegreeOfSuccess, Dnd5e, System};
+
+/// How an attack roll fared against its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AttackOutcome {
+    /// A natural 20 (or whatever the d20's crit range allows), which always hits
+    Crit,
+    Hit,
+    Miss,
+    /// A natural 1, which always misses
+    Fumble
+}
+
+/// An attack such as `d20 + 7 vs 15 => 2d6 + 4`: a roll against a target number, with
+/// damage which is only rolled on a hit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attack {
+    pub to_hit: Expression,
+    pub target: i64,
+    pub damage: Option<Expression>,
+    pub comment: Option<String>,
+    /// The limits the attack was parsed with, which its damage on a crit is also held to
+    pub limits: Limits
+}
+
+/// The rolls made for an [`Attack`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttackResult {
+    pub to_hit: Breakdown,
+    pub outcome: AttackOutcome,
+    /// How well the attack went, under systems with degrees of success
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub degree: Option<DegreeOfSuccess>,
+    /// The damage dealt, if the attack hit and has damage. A crit rolls its dice twice
+    pub damage: Option<Breakdown>
+}
+
+impl fmt::Display for AttackOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Crit => write!(f, "CRIT"),
+            Self::Hit => write!(f, "HIT"),
+            Self::Miss => write!(f, "MISS"),
+            Self::Fumble => write!(f, "MISS (natural 1)")
+        }
+    }
+}
+
+impl AttackOutcome {
+    pub fn is_hit(&self) -> bool {
+        matches!(self, Self::Crit | Self::Hit)
+    }
+}
+
+impl AttackResult {
+    /// The damage dealt, which is 0 on a miss.
+    pub fn damage_total(&self) -> i64 {
+        self.damage.as_ref().map_or(0, |x| x.total)
+    }
+}
+
+// The byte index of keyword in input outside of any label, and as a word of its own if it
+// is made of letters
+fn find_keyword(input: &str, keyword: &str) -> Option<usize> {
+    let mut in_label = false;
+    for (index, character) in input.char_indices() {
+        match character {
+            '[' => in_label = true,
+            ']' => in_label = false,
+            _ if !in_label && input[index..].starts_with(keyword) => {
+                let before = input[..index].chars().next_back();
+                let after = input[index + keyword.len()..].chars().next();
+                let is_word_character = |x: Option<char>| x.is_some_and(|x| x.is_ascii_alphanumeric());
+                let is_word = keyword.chars().all(|x| x.is_ascii_alphabetic());
+                if !is_word || (!is_word_character(before) && !is_word_character(after)) {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Attack {
+    // Whether input is an attack, rather than a plain expression
+    pub(crate) fn is_attack(input: &str) -> bool {
+        find_keyword(input, "vs").is_some()
+    }
+
+    /// Parses an attack, applying the default [`Limits`].
+    ///
+    /// ```
+    /// use roll::Attack;
+    ///
+    /// let attack = Attack::parse("d20+7 vs 15 => 2d6+4").unwrap();
+    /// assert_eq!(attack.target, 15);
+    /// assert_eq!(attack.to_string(), "d20 + 7 vs 15 => 2d6 + 4");
+    ///
+    /// let result = attack.roll(&mut rand::rng()).unwrap();
+    /// assert_eq!(result.outcome.is_hit(), result.damage.is_some());
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        Self::parse_with_limits(input, &Limits::default())
+    }
+
+    pub fn parse_with_limits(input: &str, limits: &Limits) -> Result<Self, Error> {
+        Self::from_input(input, &String::new(), limits)
+    }
+
+    pub(crate) fn from_input(input: &str, help_message: &String, limits: &Limits) -> Result<Self, Error> {
+        let (input, comment) = split_comment(input);
+        let comment = comment.filter(|x| !x.is_empty()).map(str::to_owned);
+        let Some(vs) = find_keyword(input, "vs") else {
+            return Err(ParseError::new("Expected vs and a target", None).into());
+        };
+        let (attack, damage) = match find_keyword(input, "=>") {
+            Some(x) if x > vs => (&input[..x], Some(&input[x + 2..])),
+            _ => (input, None)
+        };
+        let to_hit = Expression::from_input(attack[..vs].to_owned(), help_message, limits)?;
+        let target_input = attack[vs + 2..].trim();
+        let target = target_input.parse::<i64>()
+            .map_err(|_| ParseError::new(format!("Expected a number to roll against, not {}", target_input), None))?;
+        let damage = match damage {
+            Some(x) => Some(Expression::from_input(x.to_owned(), help_message, limits)?),
+            None => None
+        };
+        Ok(Attack { to_hit, target, damage, comment, limits: *limits })
+    }
+
+    /// Rolls to hit, and then for damage if the attack hits. A d20 showing a natural 1 always
+    /// misses, and one in its crit range (a natural 20, unless given i.e. with `d20cs>=19`)
+    /// always hits and rolls the damage dice twice.
+    ///
+    /// The doubled dice are held to the attack's limits too.
+    ///
+    /// ```
+    /// use roll::{Attack, Error, LimitError};
+    ///
+    /// // Every face is in the crit range, so this always crits
+    /// let attack = Attack::parse("d20cs>=1 vs 1 => 10000d6").unwrap();
+    /// let error = attack.roll(&mut rand::rng()).unwrap_err();
+    /// assert!(matches!(error, Error::Limit(LimitError::DicePerRoll(10000))));
+    /// ```
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<AttackResult, Error> {
+        self.roll_under(&Dnd5e, rng)
+    }
+
+    /// Rolls the attack under a system's rules. Systems with degrees of success hit on a
+    /// success and crit on a critical success, i.e. beating the DC by 10 in PF2e.
+    pub fn roll_under<R: Rng + ?Sized>(&self, system: &dyn System, rng: &mut R) -> Result<AttackResult, Error> {
+        let to_hit = self.to_hit.roll_with_breakdown(rng)?;
+        let d20s: Vec<_> = to_hit.dice().into_iter().filter(|x| x.sides == 20 && !x.dropped).collect();
+        let degree = system.degree_of_success(&to_hit, self.target);
+        let outcome;
+        if let Some(x) = degree {
+            outcome = match x {
+                DegreeOfSuccess::CriticalSuccess => AttackOutcome::Crit,
+                DegreeOfSuccess::Success => AttackOutcome::Hit,
+                DegreeOfSuccess::Failure => AttackOutcome::Miss,
+                DegreeOfSuccess::CriticalFailure => AttackOutcome::Fumble
+            };
+        }
+        else if d20s.iter().any(|x| x.crit) {
+            outcome = AttackOutcome::Crit;
+        }
+        else if d20s.iter().any(|x| x.fumble) {
+            outcome = AttackOutcome::Fumble;
+        }
+        else if to_hit.total >= self.target {
+            outcome = AttackOutcome::Hit;
+        }
+        else {
+            outcome = AttackOutcome::Miss;
+        }
+        let damage = match &self.damage {
+            Some(x) if outcome == AttackOutcome::Crit => {
+                // The crit is a new tree, so was not checked when the attack was parsed
+                let crit = x.crit(system.crit_rule());
+                crit.ast().check_limits(&self.limits)?;
+                Some(crit.roll_with_breakdown(rng)?)
+            }
+            Some(x) if outcome.is_hit() => Some(x.roll_with_breakdown(rng)?),
+            _ => None
+        };
+        Ok(AttackResult { to_hit, outcome, degree, damage })
+    }
+}
+
+impl fmt::Display for Attack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} vs {}", self.to_hit, self.target)?;
+        if let Some(x) = &self.damage {
+            write!(f, " => {}", x)?;
+        }
+        if let Some(x) = &self.comment {
+            write!(f, " # {}", x)?;
+        }
+        Ok(())
+    }
+}
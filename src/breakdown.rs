@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use crate::palette::Palette;
+use crate::error::ArithmeticError;
+use crate::damage::DamageTypeTotal;
+
+/// A single die as it was rolled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DieResult {
+    pub face: u32,
+    /// The number of sides on the die
+    pub sides: u8,
+    /// Whether the die was dropped (i.e. the lower die of 2d20dl1) and so does not count
+    pub dropped: bool,
+    /// Whether the die is a critical success (by default, its highest face)
+    pub crit: bool,
+    /// Whether the die is a fumble (by default, a 1)
+    pub fumble: bool
+}
+
+/// The binary operators an expression may contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power
+}
+
+impl Operator {
+    pub fn symbol(&self) -> char {
+        match self {
+            Self::Add => '+',
+            Self::Subtract => '-',
+            Self::Multiply => '*',
+            Self::Divide => '/',
+            Self::Power => '^'
+        }
+    }
+
+    // How tightly the operator binds, where higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Add | Self::Subtract => 1,
+            Self::Multiply | Self::Divide => 2,
+            Self::Power => 3
+        }
+    }
+
+    pub fn is_right_associative(&self) -> bool {
+        *self == Self::Power
+    }
+}
+
+/// How one part of an expression was evaluated, mirroring the shape of the expression.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Breakdown {
+    /// The total of this part of the expression
+    pub total: i64,
+    pub kind: BreakdownKind,
+    /// Whether this part was written in brackets
+    pub is_bracketed: bool,
+    /// The label given to this part, i.e. `fire` in `2d6[fire]`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub label: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BreakdownKind {
+    Operation(Operator, Box<Breakdown>, Box<Breakdown>),
+    /// Every die rolled, with any dropped dice first
+    Roll(Vec<DieResult>),
+    /// A Forged in the Dark pool of zero dice (`fitd(0)`), which is rolled as 2d6 keeping
+    /// the lowest
+    ZeroDice(Vec<DieResult>),
+    Constant,
+    /// A labelled part with resistances or vulnerabilities applied to it, alongside how
+    /// its damage type changed. The total is the one after the modifiers
+    Damage(Box<Breakdown>, DamageTypeTotal)
+}
+
+impl Breakdown {
+    pub fn new(total: i64, kind: BreakdownKind) -> Self {
+        Breakdown { total, kind, is_bracketed: false, label: None }
+    }
+
+    /// The total of every label, i.e. fire and slashing for `2d6[fire] + 1d8[slashing]`.
+    /// Parts sharing a label are summed, except for those inside another part with the same
+    /// label, which are already counted by it.
+    pub fn label_totals(&self) -> Result<BTreeMap<String, i64>, ArithmeticError> {
+        let mut totals = BTreeMap::new();
+        self.add_label_totals(&mut totals, &mut Vec::new())?;
+        Ok(totals)
+    }
+
+    // enclosing holds the labels of every part this one is inside
+    fn add_label_totals<'a>(&'a self, totals: &mut BTreeMap<String, i64>, enclosing: &mut Vec<&'a str>) -> Result<(), ArithmeticError> {
+        let label = self.label.as_deref().filter(|x| !enclosing.contains(x));
+        if let Some(x) = label {
+            let total = totals.entry(x.to_owned()).or_insert(0);
+            *total = total.checked_add(self.total).ok_or(ArithmeticError::Overflow)?;
+            enclosing.push(x);
+        }
+        match &self.kind {
+            BreakdownKind::Operation(_, x, y) => {
+                x.add_label_totals(totals, enclosing)?;
+                y.add_label_totals(totals, enclosing)?;
+            }
+            // These are the totals as rolled, before any resistances
+            BreakdownKind::Damage(x, _) => x.add_label_totals(totals, enclosing)?,
+            BreakdownKind::Roll(_) | BreakdownKind::ZeroDice(_) | BreakdownKind::Constant => {}
+        }
+        if label.is_some() {
+            enclosing.pop();
+        }
+        Ok(())
+    }
+
+    /// The damage of every type which was resisted, made vulnerable or made immune, with the
+    /// parts of each type summed.
+    pub fn damage_types(&self) -> Result<Vec<DamageTypeTotal>, ArithmeticError> {
+        let mut types: Vec<DamageTypeTotal> = Vec::new();
+        self.add_damage_types(&mut types)?;
+        Ok(types)
+    }
+
+    fn add_damage_types(&self, types: &mut Vec<DamageTypeTotal>) -> Result<(), ArithmeticError> {
+        match &self.kind {
+            BreakdownKind::Operation(_, x, y) => {
+                x.add_damage_types(types)?;
+                y.add_damage_types(types)?;
+            }
+            BreakdownKind::Damage(x, damage_type) => {
+                // Other types may be modified inside this one
+                x.add_damage_types(types)?;
+                match types.iter_mut().find(|x| x.damage_type.eq_ignore_ascii_case(&damage_type.damage_type)) {
+                    Some(x) => {
+                        x.rolled = x.rolled.checked_add(damage_type.rolled).ok_or(ArithmeticError::Overflow)?;
+                        x.total = x.total.checked_add(damage_type.total).ok_or(ArithmeticError::Overflow)?;
+                    }
+                    None => types.push(damage_type.clone())
+                }
+            }
+            BreakdownKind::Roll(_) | BreakdownKind::ZeroDice(_) | BreakdownKind::Constant => {}
+        }
+        Ok(())
+    }
+
+    /// Every die rolled in this part of the expression, from left to right.
+    pub fn dice(&self) -> Vec<&DieResult> {
+        match &self.kind {
+            BreakdownKind::Operation(_, x, y) => {
+                let mut dice = x.dice();
+                dice.extend(y.dice());
+                dice
+            }
+            BreakdownKind::Roll(dice) | BreakdownKind::ZeroDice(dice) => dice.iter().collect(),
+            BreakdownKind::Constant => Vec::new(),
+            BreakdownKind::Damage(x, _) => x.dice()
+        }
+    }
+
+    /// Writes out how the total was reached, i.e. `(X3 + 5 + 6) + 2`.
+    /// Dropped dice are marked with an X, unless skip_dropped is set in which case they are
+    /// left out. The markers, crits and fumbles are coloured from the palette, if one is
+    /// given, and crits and fumbles are followed by `CRIT` or `FUMBLE` if tag_crits is set.
+    /// Labelled parts are followed by their label and subtotal, i.e. `(3 + 4) [fire: 7]`, and
+    /// any change to them, i.e. `(3 + 4) [fire: 7 / 2 = 3]`.
+    pub fn render(&self, output: &mut String, skip_dropped: bool, palette: Option<&Palette>, tag_crits: bool) {
+        self.render_unlabelled(output, skip_dropped, palette, tag_crits);
+        if let Some(x) = &self.label {
+            *output += &format!(" [{}: {}]", x, self.total);
+        }
+    }
+
+    // As render, without the label after this part
+    fn render_unlabelled(&self, output: &mut String, skip_dropped: bool, palette: Option<&Palette>, tag_crits: bool) {
+        // A label after an operation names all of it, so keep the operation together
+        let bracketed = self.is_bracketed || (self.label.is_some() && matches!(self.kind, BreakdownKind::Operation(..)));
+        if bracketed {
+            *output += "(";
+        }
+        match &self.kind {
+            BreakdownKind::Operation(operator, x, y) => {
+                x.render(output, skip_dropped, palette, tag_crits);
+                output.push(' ');
+                output.push(operator.symbol());
+                output.push(' ');
+                y.render(output, skip_dropped, palette, tag_crits);
+            }
+            BreakdownKind::Roll(dice) | BreakdownKind::ZeroDice(dice) => {
+                let shown: Vec<&DieResult> = dice.iter().filter(|x| !(skip_dropped && x.dropped)).collect();
+                // Written brackets already group the dice
+                let grouped = shown.len() > 1 && !bracketed;
+                if grouped {
+                    output.push('(');
+                }
+                for (result_index, result) in shown.iter().enumerate() {
+                    if result_index > 0 {
+                        *output += " + ";
+                    }
+                    if result.dropped {
+                        match palette {
+                            Some(x) => Palette::paint(output, &x.dropped, "X"),
+                            None => *output += "X"
+                        }
+                    }
+                    let face = result.face.to_string();
+                    let highlight = match palette {
+                        Some(x) if result.crit => Some(&x.crit),
+                        Some(x) if result.fumble => Some(&x.fumble),
+                        _ => None
+                    };
+                    match highlight {
+                        Some(x) => Palette::paint(output, x, &face),
+                        None => *output += &face
+                    }
+                    if tag_crits && result.crit {
+                        *output += " CRIT";
+                    }
+                    else if tag_crits && result.fumble {
+                        *output += " FUMBLE";
+                    }
+                }
+                if grouped {
+                    output.push(')');
+                }
+            }
+            BreakdownKind::Constant => {
+                *output += &self.total.to_string();
+            }
+            // i.e. (3 + 4) [fire: 7 / 2 = 3], in place of the part's own label
+            BreakdownKind::Damage(x, damage_type) => {
+                x.render_unlabelled(output, skip_dropped, palette, tag_crits);
+                *output += " [";
+                damage_type.render(output);
+                *output += "]";
+            }
+        }
+        if bracketed {
+            *output += ")";
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use crate::error::ArithmeticError;
+use crate::breakdown::{Breakdown, BreakdownKind};
+
+/// How a creature treats one type of damage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DamageModifierKind {
+    /// The damage is halved, rounding down
+    Resistance,
+    /// The damage is doubled
+    Vulnerability,
+    /// The damage is ignored entirely
+    Immunity
+}
+
+/// A modifier such as `resist(fire)`, applied to every part of an expression labelled with
+/// that damage type before anything else is done with it. Labels which no modifier names,
+/// such as `attack` or `advantage`, are not treated as damage types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DamageModifier {
+    pub kind: DamageModifierKind,
+    pub damage_type: String
+}
+
+/// The damage of one type, before and after its modifiers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DamageTypeTotal {
+    pub damage_type: String,
+    /// The total rolled for this type
+    pub rolled: i64,
+    /// The modifiers which applied, in the order they were applied
+    pub modifiers: Vec<DamageModifierKind>,
+    /// The total once the modifiers were applied
+    pub total: i64
+}
+
+impl DamageModifierKind {
+    // The name used in expressions, i.e. the resist of resist(fire)
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Resistance => "resist",
+            Self::Vulnerability => "vuln",
+            Self::Immunity => "immune"
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "resist" | "resistance" => Some(Self::Resistance),
+            "vuln" | "vulnerable" | "vulnerability" => Some(Self::Vulnerability),
+            "immune" | "immunity" => Some(Self::Immunity),
+            _ => None
+        }
+    }
+
+    // The arithmetic the modifier performs, as shown in a breakdown
+    fn operation(&self) -> &'static str {
+        match self {
+            Self::Resistance => " / 2",
+            Self::Vulnerability => " * 2",
+            Self::Immunity => " * 0"
+        }
+    }
+}
+
+impl fmt::Display for DamageModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.kind.name(), self.damage_type)
+    }
+}
+
+impl DamageTypeTotal {
+    // i.e. fire: 7 / 2 = 3
+    pub(crate) fn render(&self, output: &mut String) {
+        *output += &format!("{}: {}", self.damage_type, self.rolled);
+        for modifier in &self.modifiers {
+            *output += modifier.operation();
+        }
+        *output += &format!(" = {}", self.total);
+    }
+}
+
+// Splits any modifiers such as resist(fire) off the end of an expression, returning them in
+// the order they were written
+pub(crate) fn split_damage_modifiers(input: &str) -> (&str, Vec<DamageModifier>) {
+    let mut expression = input.trim_end();
+    let mut modifiers = Vec::new();
+    while let Some(before_close) = expression.strip_suffix(')') {
+        let Some(open) = before_close.rfind('(') else {break};
+        let before_open = &before_close[..open];
+        let name_start = before_open.trim_end_matches(|x: char| x.is_ascii_alphabetic()).len();
+        let Some(kind) = DamageModifierKind::from_name(&before_open[name_start..].to_ascii_lowercase()) else {break};
+        let damage_type = before_close[open + 1..].trim();
+        if damage_type.is_empty() {
+            break;
+        }
+        modifiers.push(DamageModifier { kind, damage_type: damage_type.to_owned() });
+        expression = before_open[..name_start].trim_end();
+    }
+    modifiers.reverse();
+    (expression, modifiers)
+}
+
+// Applies any modifiers for its damage type to one labelled part of an expression, giving a
+// breakdown of the modified total which holds the original. Following 5e, immunity wins
+// outright, and otherwise resistance is applied before vulnerability
+pub(crate) fn apply_damage_modifiers(breakdown: Breakdown, modifiers: &[DamageModifier]) -> Result<Breakdown, ArithmeticError> {
+    let Some(damage_type) = breakdown.label.clone() else {
+        return Ok(breakdown);
+    };
+    let applies = |kind: DamageModifierKind| modifiers.iter().any(|x| x.kind == kind && x.damage_type.eq_ignore_ascii_case(&damage_type));
+    let mut applied = Vec::new();
+    let mut modified = breakdown.total;
+    if applies(DamageModifierKind::Immunity) {
+        applied.push(DamageModifierKind::Immunity);
+        modified = 0;
+    }
+    else {
+        if applies(DamageModifierKind::Resistance) {
+            applied.push(DamageModifierKind::Resistance);
+            modified = modified.div_euclid(2);
+        }
+        if applies(DamageModifierKind::Vulnerability) {
+            applied.push(DamageModifierKind::Vulnerability);
+            modified = modified.checked_mul(2).ok_or(ArithmeticError::Overflow)?;
+        }
+    }
+    if applied.is_empty() {
+        return Ok(breakdown);
+    }
+    let damage_type = DamageTypeTotal { damage_type, rolled: breakdown.total, modifiers: applied, total: modified };
+    Ok(Breakdown::new(modified, BreakdownKind::Damage(Box::new(breakdown), damage_type)))
+}
+
+// The modifiers which still apply inside a part with the given label, as a part inside
+// another of the same damage type is modified along with it rather than twice
+pub(crate) fn modifiers_within(modifiers: &[DamageModifier], label: Option<&str>) -> Vec<DamageModifier> {
+    modifiers.iter().filter(|x| !label.is_some_and(|y| x.damage_type.eq_ignore_ascii_case(y))).cloned().collect()
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use crate::error::{ArithmeticError, Error, ParseError};
+
+// Exact distributions get expensive quickly, so refuse to compute any which would need
+// more than this many steps rather than hanging
+const MAX_WORK: u128 = 500_000_000;
+
+/// The exact probability distribution of an expression's total.
+///
+/// Every possible total is mapped to the probability of rolling it, so statistics such as
+/// the mean can be read off without sampling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    probabilities: BTreeMap<i64, f64>
+}
+
+impl Distribution {
+    /// A distribution which always gives `value`.
+    pub fn constant(value: i64) -> Self {
+        Distribution { probabilities: BTreeMap::from([(value, 1.0)]) }
+    }
+
+    /// The probability that the total is exactly `value`.
+    pub fn probability(&self, value: i64) -> f64 {
+        self.probabilities.get(&value).copied().unwrap_or(0.0)
+    }
+
+    /// The probability that the total is at least `value`.
+    pub fn probability_at_least(&self, value: i64) -> f64 {
+        self.probabilities.range(value..).map(|(_, p)| p).sum()
+    }
+
+    /// Every possible total alongside its probability, from lowest to highest.
+    pub fn iter(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.probabilities.iter().map(|(value, p)| (*value, *p))
+    }
+
+    /// The lowest possible total.
+    pub fn min(&self) -> i64 {
+        *self.probabilities.keys().next().unwrap()
+    }
+
+    /// The highest possible total.
+    pub fn max(&self) -> i64 {
+        *self.probabilities.keys().next_back().unwrap()
+    }
+
+    /// The expected total.
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(value, p)| value as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter().map(|(value, p)| (value as f64 - mean).powi(2) * p).sum()
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    // The distribution of the sum of num_rolls dice with the given number of sides, keeping
+    // only the highest (or lowest) num_kept of them
+    pub(crate) fn of_roll(num_rolls: usize, dice_sides: u8, num_kept: usize, keep_highest: bool) -> Result<Self, Error> {
+        if num_rolls == 0 || num_kept == 0 {
+            return Ok(Self::constant(0));
+        }
+        if dice_sides == 0 {
+            return Err(ParseError::new("Dice must have at least one side", None).into());
+        }
+        let sides = dice_sides as usize;
+        let kept_distribution = if num_kept == num_rolls {
+            Self::of_sum(num_rolls, sides)?
+        }
+        else {
+            Self::of_highest(num_rolls, sides, num_kept)?
+        };
+        if keep_highest {
+            return Ok(kept_distribution);
+        }
+        // Mirroring every face (1 <-> sides) turns the lowest dice into the highest, so
+        // the kept lowest total is num_kept * (sides + 1) minus a kept highest total
+        let mirror = num_kept as i64 * (sides as i64 + 1);
+        Ok(Distribution { probabilities: kept_distribution.iter().map(|(value, p)| (mirror - value, p)).collect() })
+    }
+
+    // Adds the dice one at a time, convolving with a single die each time
+    fn of_sum(num_rolls: usize, sides: usize) -> Result<Self, Error> {
+        let work = (num_rolls as u128).pow(2) * (sides as u128).pow(2);
+        if work > MAX_WORK {
+            return Err(Error::Unsupported("Too many dice to compute the distribution exactly".to_owned()));
+        }
+        // probabilities[i] is the chance of the dice so far totalling i
+        let mut probabilities: Vec<f64> = vec![1.0];
+        let face_probability = 1.0 / sides as f64;
+        for _ in 0..num_rolls {
+            let mut next = vec![0.0; probabilities.len() + sides];
+            for (total, p) in probabilities.iter().enumerate() {
+                for face in 1..=sides {
+                    next[total + face] += p * face_probability;
+                }
+            }
+            probabilities = next;
+        }
+        Ok(Distribution {
+            probabilities: probabilities.into_iter().enumerate().filter(|(_, p)| *p > 0.0).map(|(total, p)| (total as i64, p)).collect()
+        })
+    }
+
+    // Works down from the highest face, choosing how many dice show each face. Once
+    // num_kept dice have been placed, every later (lower) die is dropped
+    fn of_highest(num_rolls: usize, sides: usize, num_kept: usize) -> Result<Self, Error> {
+        let max_kept_total = num_kept * sides;
+        let work = (sides as u128) * (num_rolls as u128).pow(2) * (max_kept_total as u128);
+        if work > MAX_WORK {
+            return Err(Error::Unsupported("Too many dice to compute the distribution exactly".to_owned()));
+        }
+        // ln(n!) for every n up to num_rolls, so that binomial coefficients do not overflow
+        let mut ln_factorial = vec![0.0; num_rolls + 1];
+        for n in 1..=num_rolls {
+            ln_factorial[n] = ln_factorial[n - 1] + (n as f64).ln();
+        }
+        let ln_sides = (sides as f64).ln();
+        // states[placed][kept_total] is the chance of that many dice having been placed so
+        // far, with the kept ones among them totalling kept_total
+        let mut states = vec![vec![0.0; max_kept_total + 1]; num_rolls + 1];
+        states[0][0] = 1.0;
+        for face in (1..=sides).rev() {
+            let mut next = vec![vec![0.0; max_kept_total + 1]; num_rolls + 1];
+            for placed in 0..=num_rolls {
+                for kept_total in 0..=max_kept_total {
+                    let p = states[placed][kept_total];
+                    if p == 0.0 {
+                        continue;
+                    }
+                    let remaining = num_rolls - placed;
+                    for showing_face in 0..=remaining {
+                        // The chance of exactly these remaining dice showing this face
+                        let ln_weight = ln_factorial[remaining] - ln_factorial[showing_face] - ln_factorial[remaining - showing_face] - showing_face as f64 * ln_sides;
+                        let newly_kept = showing_face.min(num_kept.saturating_sub(placed));
+                        next[placed + showing_face][kept_total + newly_kept * face] += p * ln_weight.exp();
+                    }
+                }
+            }
+            states = next;
+        }
+        Ok(Distribution {
+            probabilities: states[num_rolls].iter().enumerate().filter(|(_, p)| **p > 0.0).map(|(total, p)| (total as i64, *p)).collect()
+        })
+    }
+
+    // The distribution of operation(x, y) where x and y are drawn independently from self and other
+    pub(crate) fn combine(&self, other: &Self, operation: impl Fn(i64, i64) -> Result<i64, ArithmeticError>) -> Result<Self, Error> {
+        let work = self.probabilities.len() as u128 * other.probabilities.len() as u128;
+        if work > MAX_WORK {
+            return Err(Error::Unsupported("Too many possible totals to compute the distribution exactly".to_owned()));
+        }
+        let mut probabilities: BTreeMap<i64, f64> = BTreeMap::new();
+        for (x, p) in self.iter() {
+            for (y, q) in other.iter() {
+                *probabilities.entry(operation(x, y)?).or_insert(0.0) += p * q;
+            }
+        }
+        Ok(Distribution { probabilities })
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::path::PathBuf;
+
+// Errors raised by the arithmetic in an expression, rather than by its syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    // A result (or an intermediate value) did not fit in an i64
+    Overflow,
+    DivisionByZero,
+    NegativeExponent
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Arithmetic overflow"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::NegativeExponent => write!(f, "Negative exponents are not supported")
+        }
+    }
+}
+
+impl std::error::Error for ArithmeticError {}
+
+// Raised when an expression asks for more than its Limits allow.
+// Each variant carries the limit which was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    DicePerRoll(usize),
+    TotalDice(usize),
+    NestingDepth(usize),
+    ExpressionLength(usize),
+    OutputLength(usize)
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DicePerRoll(x) => write!(f, "A single roll may contain at most {} dice", x),
+            Self::TotalDice(x) => write!(f, "An expression may roll at most {} dice in total", x),
+            Self::NestingDepth(x) => write!(f, "Brackets or tables may be nested at most {} deep", x),
+            Self::ExpressionLength(x) => write!(f, "An expression may be at most {} characters long", x),
+            Self::OutputLength(x) => write!(f, "The output may be at most {} bytes long", x)
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+// Raised when the input is not a valid expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    // The offset, in characters, into the input at which the problem was found, if known
+    pub position: Option<usize>
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, position: Option<usize>) -> Self {
+        ParseError { message: message.into(), position }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(x) => write!(f, "{} at character {}", self.message, x + 1),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Any error from parsing or rolling an expression, or from reading a sheet or table.
+///
+/// Unlike a boxed error, it may be sent between threads and held across an `.await`, and
+/// each kind of error can be told apart with a `match`.
+///
+/// ```
+/// use roll::{Error, Expression, LimitError, Limits};
+///
+/// fn is_send_sync<T: Send + Sync>(_: &T) {}
+/// let error = Expression::parse_with_limits("200d6", &Limits::default().max_dice_per_roll(100)).unwrap_err();
+/// is_send_sync(&error);
+/// assert!(matches!(error, Error::Limit(LimitError::DicePerRoll(100))));
+///
+/// // Each of several expressions is rolled in turn, and an error says which one failed
+/// let options = roll::OutputOptions::default();
+/// let error = roll::consume_input_to_output("d6, d6/0".to_owned(), &String::new(), &options, &Limits::default()).unwrap_err();
+/// assert!(matches!(&error, Error::InExpression(2, x) if matches!(**x, Error::Arithmetic(_))));
+/// assert_eq!(error.to_string(), "In expression 2: Division by zero");
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Limit(LimitError),
+    Arithmetic(ArithmeticError),
+    /// Something which may be asked for but cannot yet be done, such as the exact
+    /// distribution of a very large pool
+    Unsupported(String),
+    /// A file which could not be read, and why
+    File(PathBuf, std::io::Error),
+    /// An error in one of several comma-separated expressions, counting from 1
+    InExpression(usize, Box<Error>)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(x) => write!(f, "{}", x),
+            Self::Limit(x) => write!(f, "{}", x),
+            Self::Arithmetic(x) => write!(f, "{}", x),
+            Self::Unsupported(x) => write!(f, "{}", x),
+            Self::File(path, x) => write!(f, "Could not read {}: {}", path.display(), x),
+            Self::InExpression(index, x) => write!(f, "In expression {}: {}", index, x)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::File(_, x) => Some(x),
+            Self::InExpression(_, x) => Some(x.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<LimitError> for Error {
+    fn from(error: LimitError) -> Self {
+        Self::Limit(error)
+    }
+}
+
+impl From<ArithmeticError> for Error {
+    fn from(error: ArithmeticError) -> Self {
+        Self::Arithmetic(error)
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt;
+use std::str::FromStr;
+use rand::Rng;
+use crate::tree::{split_comment, ASTInput, AST, CritRule};
+use crate::error::Error;
+use crate::limits::Limits;
+use crate::breakdown::Breakdown;
+use crate::distribution::Distribution;
+use crate::damage::{split_damage_modifiers, DamageModifier};
+
+/// A parsed dice expression, such as `4d6dl1 + 3`.
+///
+/// Parsing is done once, after which the expression may be rolled any number of times.
+/// Parts of an expression may be labelled, as in `2d6[fire] + 1d8[slashing]`, and anything
+/// after a `#` is kept as a comment. Labels double as damage types, which may be resisted
+/// (halved), made vulnerable (doubled) or made immune, as in `2d6[fire] + 4[slashing] resist(fire)`.
+///
+/// ```
+/// use roll::Expression;
+///
+/// let expression: Expression = "2d20dl1 + 5".parse().unwrap();
+/// let mut rng = rand::rng();
+/// let total = expression.roll(&mut rng).unwrap();
+/// assert!((6..=25).contains(&total));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    ast: AST,
+    // Resistances and the like, applied to the labelled parts of the total
+    modifiers: Vec<DamageModifier>,
+    comment: Option<String>
+}
+
+impl Expression {
+    /// Parses an expression, applying the default [`Limits`].
+    ///
+    /// 5e's advantage and disadvantage may be written as `adv` and `dis`, or as `d20a` and
+    /// `d20d`, and are labelled as such.
+    ///
+    /// ```
+    /// use roll::Expression;
+    ///
+    /// let expression = Expression::parse("adv + 5").unwrap();
+    /// assert_eq!(expression.to_string(), "2d20dl1[advantage] + 5");
+    /// assert_eq!(expression, Expression::parse("d20a + 5").unwrap());
+    /// // Keeping the highest die is the same as dropping the others
+    /// assert_eq!(Expression::parse("3d20kh1").unwrap().distribution().unwrap(), Expression::parse("3d20dl2").unwrap().distribution().unwrap());
+    /// ```
+    ///
+    /// Numbers must fit in an `i64`, and dice must have at least one side.
+    ///
+    /// ```
+    /// use roll::{ArithmeticError, Error, Expression};
+    ///
+    /// let error = Expression::parse("9223372036854775808").unwrap_err();
+    /// assert!(matches!(error, Error::Arithmetic(ArithmeticError::Overflow)));
+    /// assert!(Expression::parse("18446744073709551615 + 1").is_err());
+    /// assert!(matches!(Expression::parse("d"), Err(Error::Parse(_))));
+    /// assert!(matches!(Expression::parse("2d0 + 1"), Err(Error::Parse(_))));
+    /// ```
+    ///
+    /// Whitespace is ignored, except that it may not separate two numbers, which are more
+    /// likely two expressions run together than one.
+    ///
+    /// ```
+    /// use roll::{Error, Expression};
+    ///
+    /// assert_eq!(Expression::parse(" 2 d 6 +3").unwrap().to_string(), "2d6 + 3");
+    /// let Err(Error::Parse(error)) = Expression::parse("d20+5 2d6+3") else { panic!() };
+    /// assert_eq!(error.position, Some(6));
+    /// assert!(Expression::parse("3 4").is_err());
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        Self::parse_with_limits(input, &Limits::default())
+    }
+
+    /// Parses an expression, rejecting it if it asks for more than `limits` allow.
+    pub fn parse_with_limits(input: &str, limits: &Limits) -> Result<Self, Error> {
+        Self::from_input(input.to_owned(), &String::new(), limits)
+    }
+
+    // As parse_with_limits, but the help message is printed if the input is not understood
+    pub(crate) fn from_input(input: String, help_message: &String, limits: &Limits) -> Result<Self, Error> {
+        let (body, comment) = split_comment(&input);
+        let comment = comment.filter(|x| !x.is_empty()).map(str::to_owned);
+        let (body, modifiers) = split_damage_modifiers(body);
+        let mut ast_input = ASTInput::from_string(body.to_owned(), help_message, limits)?;
+        let ast = AST::consume_input(&mut ast_input)?;
+        ast.check_limits(limits)?;
+        Ok(Expression { ast, modifiers, comment })
+    }
+
+    /// The parsed tree behind this expression.
+    pub fn ast(&self) -> &AST {
+        &self.ast
+    }
+
+    /// The damage of this expression on a critical hit, as `crit(...)` and the like would
+    /// give. Only the dice are changed, so resistances and comments are kept.
+    ///
+    /// ```
+    /// use roll::{CritRule, Expression};
+    ///
+    /// let expression = Expression::parse("2d6 + 3").unwrap();
+    /// assert_eq!(expression.crit(CritRule::DoubleDice), Expression::parse("crit(2d6 + 3)").unwrap());
+    /// assert_eq!(expression.crit(CritRule::DoubleDice).to_string(), "(4d6 + 3)[crit]");
+    /// assert_eq!(expression.crit(CritRule::MaxDice).to_string(), "(12 + 2d6 + 3)[crit]");
+    /// assert_eq!(expression.crit(CritRule::DoubleTotal).to_string(), "((2d6 + 3) * 2)[crit]");
+    ///
+    /// // A label after crit(...), such as a damage type, is used in place of crit
+    /// assert_eq!(Expression::parse("crit(2d6 + 3)[slashing]").unwrap().to_string(), "(4d6 + 3)[slashing]");
+    /// let expression = Expression::parse("crit(2d6)[fire] + 3 resist(fire)").unwrap();
+    /// assert_eq!(expression.to_string(), "4d6[fire] + 3 resist(fire)");
+    /// let breakdown = expression.roll_with_breakdown(&mut rand::rng()).unwrap();
+    /// let fire = &breakdown.damage_types().unwrap()[0];
+    /// assert_eq!(breakdown.total, fire.rolled / 2 + 3);
+    /// assert!(Expression::parse("crit(2d6[fire])[cold]").is_err());
+    /// ```
+    pub fn crit(&self, rule: CritRule) -> Self {
+        Expression { ast: self.ast.crit(rule), ..self.clone() }
+    }
+
+    /// The resistances, vulnerabilities and immunities applied to the expression.
+    pub fn modifiers(&self) -> &[DamageModifier] {
+        &self.modifiers
+    }
+
+    /// The comment after the expression, if any, i.e. `sneak attack` in `3d6 # sneak attack`.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Rolls the expression, returning only its total.
+    ///
+    /// Dice are summed as they are rolled rather than stored, so this is the way to roll
+    /// very large pools. Only as many of the dice as are kept or dropped, whichever is
+    /// fewer, are remembered, and the total is the same as [`Expression::roll_with_breakdown`]
+    /// would give from the same dice.
+    ///
+    /// ```
+    /// use rand::{rngs::StdRng, SeedableRng};
+    /// use roll::Expression;
+    ///
+    /// // More dice kept than dropped, more dropped than kept, as many of each and none kept
+    /// for notation in ["4d6dl1", "4d6dh1", "5d8kh4", "5d8kl4", "10d6dl8", "10d6dh9", "10d20kh2", "10d20kl1", "6d6dl3", "6d6kh3", "3d6dl5", "3d6dh3", "3d6kh0"] {
+    ///     let expression = Expression::parse(notation).unwrap();
+    ///     for seed in 0..50 {
+    ///         let total = expression.roll(&mut StdRng::seed_from_u64(seed)).unwrap();
+    ///         let breakdown = expression.roll_with_breakdown(&mut StdRng::seed_from_u64(seed)).unwrap();
+    ///         assert_eq!(total, breakdown.total, "{} with seed {}", notation, seed);
+    ///     }
+    /// }
+    /// assert_eq!(Expression::parse("3d6dl5").unwrap().roll(&mut rand::rng()).unwrap(), 0);
+    /// ```
+    ///
+    /// Arithmetic which cannot be done, or whose result does not fit in an `i64`, is an error.
+    ///
+    /// ```
+    /// use roll::{ArithmeticError, Error, Expression};
+    ///
+    /// let error = |x: &str| match Expression::parse(x).unwrap().roll(&mut rand::rng()) {
+    ///     Err(Error::Arithmetic(x)) => x,
+    ///     other => panic!("{} gave {:?}", x, other)
+    /// };
+    /// let total = |x: &str| Expression::parse(x).unwrap().roll(&mut rand::rng()).unwrap();
+    /// assert_eq!(error("d6/0"), ArithmeticError::DivisionByZero);
+    /// assert_eq!(error("2^(0-1)"), ArithmeticError::NegativeExponent);
+    /// assert_eq!(error("2^63"), ArithmeticError::Overflow);
+    /// assert_eq!(error("(0 - 9223372036854775807 - 1) / (0 - 1)"), ArithmeticError::Overflow);
+    /// // Exponents too large for a u32 leave only a few bases which do not overflow
+    /// assert_eq!(error("2^4294967296"), ArithmeticError::Overflow);
+    /// assert_eq!(total("0^4294967296"), 0);
+    /// assert_eq!(total("1^4294967296"), 1);
+    /// assert_eq!(total("(0-1)^4294967296"), 1);
+    /// assert_eq!(total("(0-1)^4294967297"), -1);
+    /// ```
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error> {
+        // Modifiers are shown in the breakdown of each part they change
+        if !self.modifiers.is_empty() {
+            return Ok(self.roll_with_breakdown(rng)?.total);
+        }
+        self.ast.compute(rng)
+    }
+
+    /// Rolls the expression, recording every die so that the total can be explained.
+    ///
+    /// ```
+    /// use roll::Expression;
+    ///
+    /// let expression = Expression::parse("4d6dl1").unwrap();
+    /// let breakdown = expression.roll_with_breakdown(&mut rand::rng()).unwrap();
+    /// assert_eq!(breakdown.dice().len(), 4);
+    /// assert_eq!(breakdown.dice().iter().filter(|x| x.dropped).count(), 1);
+    ///
+    /// // Labelled parts can be totalled separately
+    /// let expression = Expression::parse("2d6[fire] + 1d8[slashing] + 2[slashing]").unwrap();
+    /// let totals = expression.roll_with_breakdown(&mut rand::rng()).unwrap().label_totals().unwrap();
+    /// assert!((2..=12).contains(&totals["fire"]));
+    /// assert!((3..=10).contains(&totals["slashing"]));
+    ///
+    /// // Resisting fire halves it, and only it
+    /// let expression = Expression::parse("7[fire] + 5[cold] + 1 resist(fire)").unwrap();
+    /// assert_eq!(expression.roll_with_breakdown(&mut rand::rng()).unwrap().total, 3 + 5 + 1);
+    /// ```
+    ///
+    /// Each labelled part is modified before anything else is done with it, and a part inside
+    /// another of the same type is modified along with it.
+    ///
+    /// ```
+    /// use roll::{DamageModifierKind, Expression};
+    ///
+    /// let total = |x: &str| Expression::parse(x).unwrap().roll_with_breakdown(&mut rand::rng()).unwrap().total;
+    /// assert_eq!(total("20 - 4[fire] resist(fire)"), 18);
+    /// assert_eq!(total("2 * 4[fire] resist(fire)"), 4);
+    /// assert_eq!(total("12 / 4[fire] vuln(fire)"), 1);
+    /// // Fire is halved to 2 and 1, and then the cold around the 1 is halved
+    /// assert_eq!(total("4[fire] + (3[fire] + 2)[cold] resist(fire) resist(cold)"), 2 + (1 + 2) / 2);
+    /// assert_eq!(total("(3[fire] + 2)[fire] resist(fire)"), 2);
+    /// assert_eq!(total("(3[fire] + 4[cold])[acid] immune(fire) vuln(ACID)"), 8);
+    /// assert_eq!(total("10[fire] + 8 [slashing] resist(slashing)"), 10 + 4);
+    ///
+    /// // Labels which no modifier names are not damage types
+    /// let breakdown = Expression::parse("20[attack] + 4[fire] + 1[fire] resist(fire)").unwrap().roll_with_breakdown(&mut rand::rng()).unwrap();
+    /// assert_eq!(breakdown.total, 20 + 2 + 0);
+    /// let types = breakdown.damage_types().unwrap();
+    /// assert_eq!(types.len(), 1);
+    /// assert_eq!((types[0].damage_type.as_str(), types[0].rolled, types[0].total), ("fire", 5, 2));
+    /// assert_eq!(types[0].modifiers, [DamageModifierKind::Resistance]);
+    ///
+    /// let mut text = String::new();
+    /// breakdown.render(&mut text, false, None, false);
+    /// assert_eq!(text, "20 [attack: 20] + 4 [fire: 4 / 2 = 2] + 1 [fire: 1 / 2 = 0]");
+    /// ```
+    pub fn roll_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error> {
+        self.ast.compute_with_modifiers(rng, &self.modifiers)
+    }
+
+    /// The exact distribution of the expression's total.
+    ///
+    /// ```
+    /// use roll::Expression;
+    ///
+    /// let distribution = Expression::parse("2d6").unwrap().distribution().unwrap();
+    /// assert!((distribution.mean() - 7.0).abs() < 1e-9);
+    /// assert!((distribution.probability(12) - 1.0 / 36.0).abs() < 1e-9);
+    /// ```
+    pub fn distribution(&self) -> Result<Distribution, Error> {
+        if !self.modifiers.is_empty() {
+            return Err(Error::Unsupported("Distributions cannot yet account for resistances or vulnerabilities".to_owned()));
+        }
+        self.ast.distribution()
+    }
+}
+
+/// Prints the expression in its canonical form, i.e. `4d6dl1 + 3`, which parses back to
+/// an equal expression.
+///
+/// ```
+/// use roll::Expression;
+///
+/// let expression = Expression::parse("((4d6dl1))+(3)").unwrap();
+/// assert_eq!(expression.to_string(), "4d6dl1 + 3");
+/// assert_eq!(Expression::parse(&expression.to_string()).unwrap(), expression);
+///
+/// // A label set apart at the end names the whole expression, unless parts of it are
+/// // labelled already
+/// assert_eq!(Expression::parse("d20 + 5 [attack]").unwrap().to_string(), "(d20 + 5)[attack]");
+/// assert_eq!(Expression::parse("10[fire] + 8 [slashing]").unwrap().to_string(), "10[fire] + 8[slashing]");
+/// assert_eq!(Expression::parse("2d6[fire] + 1d8 [slashing]").unwrap(), Expression::parse("2d6[fire] + 1d8[slashing]").unwrap());
+///
+/// let expression = Expression::parse("(2d6 + 1)[cold fire]*2 Resistance(fire) #  ray of frost").unwrap();
+/// assert_eq!(expression.to_string(), "(2d6 + 1)[cold fire] * 2 resist(fire) # ray of frost");
+/// assert_eq!(Expression::parse(&expression.to_string()).unwrap(), expression);
+/// ```
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ast)?;
+        for modifier in &self.modifiers {
+            write!(f, " {}", modifier)?;
+        }
+        if let Some(x) = &self.comment {
+            write!(f, " # {}", x)?;
+        }
+        Ok(())
+    }
+}
+
+// Expressions are stored as their canonical notation, which is far more stable than the
+// shape of the tree behind them, and are checked against the default limits on the way in
+#[cfg(feature = "serde")]
+impl serde::Serialize for Expression {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Expression {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        Expression::parse(&input).map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input)
+    }
+}
@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::fmt;
+use rand::Rng;
+use crate::error::{ArithmeticError, Error, ParseError};
+use crate::breakdown::Breakdown;
+use crate::expression::Expression;
+use crate::limits::Limits;
+
+// How many times tied creatures re-roll before they are left in the order they were given,
+// as creatures with the same constant initiative would never stop
+const MAX_TIEBREAKS: usize = 10;
+
+/// How to order creatures whose initiative totals are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TieBreak {
+    /// The higher modifier goes first, and any still tied re-roll
+    #[default]
+    Modifier,
+    /// Tied creatures re-roll their initiative, and the higher re-roll goes first
+    Reroll
+}
+
+/// A creature taking part in combat, such as `goblin=d20+2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combatant {
+    pub name: String,
+    pub expression: Expression
+}
+
+/// A creature's place in the initiative order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InitiativeEntry {
+    pub name: String,
+    pub roll: Breakdown,
+    /// The part of the total not from dice, i.e. the 2 of `d20 + 2`
+    pub modifier: i64,
+    /// The re-rolls which broke a tie, if any were needed, with any which were tied again
+    /// first
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub tiebreaks: Vec<i64>
+}
+
+/// Every creature in a combat, in the order they act, and whose turn it is.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Initiative {
+    pub entries: Vec<InitiativeEntry>,
+    /// The index into entries of the creature whose turn it is
+    pub turn: usize,
+    /// The current round, counting from 1
+    pub round: u32
+}
+
+impl Combatant {
+    /// Parses a `name=expression` entry, applying the default [`Limits`].
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        Self::parse_with_limits(input, &Limits::default())
+    }
+
+    pub fn parse_with_limits(input: &str, limits: &Limits) -> Result<Self, Error> {
+        let Some((name, expression)) = input.split_once('=') else {
+            return Err(ParseError::new(format!("Expected name=expression, not {}", input.trim()), None).into());
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ParseError::new("Expected a name before =", None).into());
+        }
+        let expression = Expression::parse_with_limits(expression, limits)?;
+        Ok(Combatant { name: name.to_owned(), expression })
+    }
+}
+
+impl InitiativeEntry {
+    // The order the entries are sorted in, from the first to act to the last
+    fn compare(&self, other: &Self, tie_break: TieBreak) -> Ordering {
+        let by_modifier = match tie_break {
+            TieBreak::Modifier => other.modifier.cmp(&self.modifier),
+            TieBreak::Reroll => Ordering::Equal
+        };
+        other.roll.total.cmp(&self.roll.total)
+            .then(by_modifier)
+            .then(other.tiebreaks.cmp(&self.tiebreaks))
+    }
+}
+
+impl Initiative {
+    /// Rolls initiative for every combatant and sorts them, highest first. Ties are broken
+    /// as given, re-rolling for as long as creatures are still tied (up to 10 times), and
+    /// creatures which are tied even then keep the order they were given in.
+    ///
+    /// ```
+    /// use roll::{Combatant, Initiative, TieBreak};
+    ///
+    /// let combatants = vec![Combatant::parse("goblin=d20+2").unwrap(), Combatant::parse("Aria=d20+5").unwrap()];
+    /// let mut initiative = Initiative::roll(&combatants, TieBreak::Modifier, &mut rand::rng()).unwrap();
+    /// assert!(initiative.entries[0].roll.total >= initiative.entries[1].roll.total);
+    ///
+    /// let first = initiative.current().name.clone();
+    /// initiative.advance();
+    /// initiative.advance();
+    /// assert_eq!(initiative.current().name, first);
+    /// assert_eq!(initiative.round, 2);
+    ///
+    /// // Creatures which can only tie give up re-rolling in the end
+    /// let combatants = vec![Combatant::parse("goblin=5").unwrap(), Combatant::parse("orc=5").unwrap()];
+    /// let initiative = Initiative::roll(&combatants, TieBreak::Reroll, &mut rand::rng()).unwrap();
+    /// assert_eq!(initiative.entries[0].name, "goblin");
+    /// assert_eq!(initiative.entries[0].tiebreaks, [5; 10]);
+    /// ```
+    pub fn roll<R: Rng + ?Sized>(combatants: &[Combatant], tie_break: TieBreak, rng: &mut R) -> Result<Self, Error> {
+        if combatants.is_empty() {
+            return Err(ParseError::new("Expected at least one name=expression", None).into());
+        }
+        let mut entries = Vec::with_capacity(combatants.len());
+        for combatant in combatants {
+            let roll = combatant.expression.roll_with_breakdown(rng)?;
+            let mut dice: i64 = 0;
+            for die in roll.dice().into_iter().filter(|x| !x.dropped) {
+                dice = dice.checked_add(die.face.into()).ok_or(ArithmeticError::Overflow)?;
+            }
+            let modifier = roll.total.checked_sub(dice).ok_or(ArithmeticError::Overflow)?;
+            entries.push(InitiativeEntry { name: combatant.name.clone(), roll, modifier, tiebreaks: Vec::new() });
+        }
+        // Only those still tied re-roll, each with their own expression, until none are
+        for _ in 0..MAX_TIEBREAKS {
+            let tied: Vec<bool> = (0..entries.len()).map(|index| {
+                entries.iter().enumerate().any(|(other, x)| other != index && x.compare(&entries[index], tie_break) == Ordering::Equal)
+            }).collect();
+            if !tied.contains(&true) {
+                break;
+            }
+            for (index, is_tied) in tied.into_iter().enumerate() {
+                if is_tied {
+                    entries[index].tiebreaks.push(combatants[index].expression.roll(rng)?);
+                }
+            }
+        }
+        entries.sort_by(|x, y| x.compare(y, tie_break));
+        Ok(Initiative { entries, turn: 0, round: 1 })
+    }
+
+    /// The creature whose turn it is.
+    pub fn current(&self) -> &InitiativeEntry {
+        &self.entries[self.turn]
+    }
+
+    /// Moves on to the next creature's turn, starting a new round after the last.
+    pub fn advance(&mut self) -> &InitiativeEntry {
+        self.turn += 1;
+        if self.turn == self.entries.len() {
+            self.turn = 0;
+            self.round += 1;
+        }
+        self.current()
+    }
+}
+
+impl fmt::Display for Initiative {
+    // One creature per line, marking whose turn it is, i.e. > 1. Aria: 19 (+5)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Round {}", self.round)?;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let marker = if index == self.turn { '>' } else { ' ' };
+            write!(f, "\n{} {}. {}: {} ({:+})", marker, index + 1, entry.name, entry.roll.total, entry.modifier)?;
+            if !entry.tiebreaks.is_empty() {
+                let tiebreaks: Vec<String> = entry.tiebreaks.iter().map(i64::to_string).collect();
+                write!(f, " [tiebreak {}]", tiebreaks.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
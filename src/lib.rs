@@ -1,42 +1,279 @@
+//! Rolls dice expressions such as `4d6dl1 + 3`.
+//!
+//! Parse an [`Expression`] once, then roll it as many times as needed, either for just its
+//! total or with a [`Breakdown`] of every die. Its exact [`Distribution`] is also available.
+//!
+//! # Serialization
+//!
+//! With the `serde` feature enabled, expressions and their results can be serialized.
+//! Using JSON as an example:
+//!
+//! - [`Expression`] is a string in its canonical notation, i.e. `"4d6dl1 + 3"`. Prefer this
+//!   for storing expressions, as it does not depend on how the parser builds its tree.
+//! - [`AST`] is `{"ast_type": ASType, "is_bracketed": bool, "label": "fire"}`, where the
+//!   label is left out if there is none, and [`ASType`] is one of
+//!   `{"add": [AST, AST]}` (likewise `subtract`, `multiply`, `divide` and `power`) or
+//!   `{"roll_or_constant": RollOrConstant}`.
+//! - [`RollOrConstant`] is `{"roll": Roll}` or `{"const": {"constant_result": 3}}`.
+//! - [`Roll`] is `{"num_rolls": 4, "dice_sides": 6, "drop_die": DropDie, "crit":
+//!   FaceComparison, "fumble": FaceComparison}`, where [`DropDie`] is `null`,
+//!   `{"drop_lowest": 1}`, `{"drop_highest": 1}`, `{"keep_highest": 1}` or
+//!   `{"keep_lowest": 1}` and [`FaceComparison`] is `null` (the
+//!   highest or lowest face), `{"equal": 20}`, `{"at_least": 19}` or `{"at_most": 2}`.
+//!   A Forged in the Dark pool of zero dice also has `"zero_dice": true`.
+//! - [`Breakdown`] is `{"total": 15, "kind": BreakdownKind, "is_bracketed": bool, "label":
+//!   "fire"}`, with the label likewise optional, where [`BreakdownKind`] is
+//!   `{"operation": ["add", Breakdown, Breakdown]}`,
+//!   `{"roll": [{"face": 3, "sides": 6, "dropped": true, "crit": false, "fumble": false}, ...]}`,
+//!   `{"zero_dice": [...]}` (the dice of `fitd(0)`, as for a roll), `"constant"` or
+//!   `{"damage": [Breakdown, DamageTypeTotal]}`, the last wrapping a labelled part whose
+//!   damage type was modified.
+//! - [`DamageTypeTotal`] is `{"damage_type": "fire", "rolled": 7, "modifiers": ["resistance"],
+//!   "total": 3}`, where the modifiers may also be `"vulnerability"` and `"immunity"`.
+//! - [`AttackResult`] is `{"to_hit": Breakdown, "outcome": AttackOutcome, "damage": Breakdown}`,
+//!   where [`AttackOutcome`] is `"crit"`, `"hit"`, `"miss"` or `"fumble"` and the damage is
+//!   `null` unless the attack hit. Under PF2e there is also a `"degree"`, one of
+//!   `"critical_success"`, `"success"`, `"failure"` or `"critical_failure"`.
+//! - [`FitdOutcome`], as read by [`ForgedInTheDark::outcome`], is `"critical"`,
+//!   `"full_success"`, `"partial_success"` or `"bad_outcome"`.
+//! - [`Initiative`] is `{"entries": [InitiativeEntry, ...], "turn": 0, "round": 1}`, where an
+//!   [`InitiativeEntry`] is `{"name": "goblin", "roll": Breakdown, "modifier": 2,
+//!   "tiebreaks": [12, 7]}`, leaving out the tiebreaks if there were none.
+//! - [`NarrativeResult`] is `{"dice": [{"die": "ability", "face": SymbolSet}, ...], "net":
+//!   SymbolSet}`, where a [`SymbolSet`] maps each [`Symbol`] to its count, i.e.
+//!   `{"success": 1, "triumph": 1}`, and a blank face is `{}`.
+//!
+//! ```
+//! # #[cfg(feature = "serde")] {
+//! use roll::{Breakdown, Expression};
+//!
+//! let expression: Expression = serde_json::from_str("\"4d6dl1 + 3\"").unwrap();
+//! assert_eq!(serde_json::to_string(&expression).unwrap(), "\"4d6dl1 + 3\"");
+//!
+//! let ast = serde_json::to_string(expression.ast()).unwrap();
+//! assert_eq!(&serde_json::from_str::<roll::AST>(&ast).unwrap(), expression.ast());
+//!
+//! let breakdown = expression.roll_with_breakdown(&mut rand::rng()).unwrap();
+//! let saved = serde_json::to_string(&breakdown).unwrap();
+//! assert_eq!(serde_json::from_str::<Breakdown>(&saved).unwrap(), breakdown);
+//!
+//! let combatants = [roll::Combatant::parse("goblin=d20+2").unwrap()];
+//! let initiative = roll::Initiative::roll(&combatants, roll::TieBreak::Modifier, &mut rand::rng()).unwrap();
+//! let saved = serde_json::to_value(&initiative).unwrap();
+//! assert_eq!(saved["entries"][0]["name"], "goblin");
+//! assert_eq!(saved["entries"][0]["modifier"], 2);
+//!
+//! // Only systems with degrees of success give a degree
+//! let attack = roll::Attack::parse("d20 + 7 vs 15 => 2d6 + 4").unwrap();
+//! let saved = serde_json::to_value(attack.roll(&mut rand::rng()).unwrap()).unwrap();
+//! assert!(saved.get("degree").is_none());
+//! let saved = serde_json::to_value(attack.roll_under(&roll::Pf2e, &mut rand::rng()).unwrap()).unwrap();
+//! assert!(saved["degree"].is_string());
+//! # }
+//! ```
+
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 mod tree;
 mod roll;
-use tree::{ASTInput, AST};
+mod error;
+mod limits;
+mod breakdown;
+mod distribution;
+mod expression;
+mod palette;
+mod damage;
+mod attack;
+mod system;
+mod narrative;
+mod initiative;
+mod sheet;
+#[cfg(feature = "files")]
+mod table;
+mod output;
+#[cfg(feature = "wasm")]
+mod wasm;
+use tree::{split_comment, split_outside_labels};
+pub use error::{ArithmeticError, Error, LimitError, ParseError};
+pub use limits::Limits;
+pub use breakdown::{Breakdown, BreakdownKind, DieResult, Operator};
+pub use attack::{Attack, AttackOutcome, AttackResult};
+pub use system::{system_by_name, Check, DegreeOfSuccess, FitdOutcome, System, SYSTEM_NAMES, Dnd5e, Pf2e, Pbta, ForgedInTheDark, YearZero};
+pub use sheet::Sheet;
+#[cfg(feature = "files")]
+pub use table::{Table, TableEntry, TableFormat, TableRoll};
+pub use initiative::{Combatant, Initiative, InitiativeEntry, TieBreak};
+pub use narrative::{NarrativeDie, NarrativeDieResult, NarrativePool, NarrativeResult, Symbol, SymbolSet};
+pub use damage::{DamageModifier, DamageModifierKind, DamageTypeTotal};
+pub use distribution::Distribution;
+pub use expression::Expression;
+pub use palette::Palette;
+pub use output::OutputOptions;
+pub use tree::{AST, ASType, CritRule};
+pub use roll::{Roll, RollOrConstant, ConstantRoll, DropDie, FaceComparison};
+
+/// Rolls the expressions in `input`, which are separated by commas, and writes out their results.
+///
+/// A single expression gives its breakdown and total, i.e. `(3 + 5) + 2 => 10`. Several give
+/// one labelled line each, i.e. `d20 + 5: 12 + 5 => 17`, optionally followed by their grand total.
+/// A trailing `# comment` is repeated at the end of the output.
+///
+/// With a [`System`] in the options, its shorthands may be used and its reading of each
+/// result follows the total, i.e. `(3 + 5) => 8 (partial)` under PbtA.
+///
+/// With a [`Sheet`] in the options, the names on it may be used in expressions, i.e.
+/// `d20 + athletics`.
+///
+/// An expression may also be an [`Attack`], i.e. `d20 + 7 vs 15 => 2d6 + 4`, which gives
+/// whether it hit and then any damage. Attacks count towards the grand total by their damage.
+///
+/// ```
+/// use roll::{consume_input_to_output, Limits, OutputOptions};
+///
+/// let options = OutputOptions { short_output: true, grand_total: true, ..OutputOptions::default() };
+/// let output = consume_input_to_output("5, 2 * 3".to_owned(), &String::new(), &options, &Limits::default()).unwrap();
+/// assert_eq!(output, "5: 5\n2 * 3: 6\nTotal: 11");
+/// ```
+pub fn consume_input_to_output(input: String, help_message: &String, options: &OutputOptions, limits: &Limits) -> Result<String, Error> {
+    let input = match &options.system {
+        Some(x) => x.expand_shorthands(&input),
+        None => input
+    };
+    // Narrative pools are made of letters, so have no names to look up
+    let input = match &options.sheet {
+        Some(x) if !options.narrative => x.expand(&input)?,
+        _ => input
+    };
+    // Commas inside labels or the comment do not separate expressions
+    let (body, comment) = split_comment(&input);
+    let comment = comment.filter(|x| !x.is_empty()).map(|x| " # ".to_owned() + x).unwrap_or_default();
+    let parts = split_outside_labels(body, ',');
+    if parts.len() == 1 {
+        let (output, _) = roll_to_output(body.to_owned(), help_message, options, options.echo, limits)?;
+        return Ok(output + &comment);
+    }
+    let mut lines: Vec<String> = Vec::with_capacity(parts.len() + 1);
+    let mut grand_total: i64 = 0;
+    for (part_index, part) in parts.into_iter().enumerate() {
+        // Positions in an error are counted from the start of the part, so say which part it was
+        let (output, total) = roll_to_output(part.to_owned(), help_message, options, true, limits)
+            .map_err(|x| Error::InExpression(part_index + 1, Box::new(x)))?;
+        lines.push(output);
+        grand_total = grand_total.checked_add(total).ok_or(ArithmeticError::Overflow)?;
+    }
+    if options.grand_total {
+        lines.push("Total: ".to_owned() + &grand_total.to_string());
+    }
+    Ok(lines.join("\n") + &comment)
+}
 
-pub fn consume_input_to_output(input: String, help_message: &String, skip_dropped: bool, short_output: bool, colour: bool) -> Result<String, Box<dyn std::error::Error>> {
-    let mut ast_input = ASTInput::from_string(input, help_message)?;
-    let mut ast = AST::consume_input(&mut ast_input)?;
+// Rolls a single expression, giving both its output and its total
+fn roll_to_output(input: String, help_message: &String, options: &OutputOptions, echo: bool, limits: &Limits) -> Result<(String, i64), Error> {
+    if options.narrative {
+        return narrative_to_output(&input, options, echo, limits);
+    }
+    if Attack::is_attack(&input) {
+        return attack_to_output(&input, help_message, options, echo, limits);
+    }
+    let expression = Expression::from_input(input, help_message, limits)?;
     let mut rng = rand::rng();
-    let running_total: i64;
-    // And then we build the output
     let mut output: String = String::new();
-    if short_output {
-        running_total = ast.compute(&mut rng)?;
+    // Show the expression as it was understood
+    if echo {
+        output += &(expression.to_string() + ": ");
+    }
+    // A system needs every die to read the result
+    if options.short_output && options.system.is_none() {
+        let total = expression.roll(&mut rng)?;
+        output += &total.to_string();
+        return Ok((output, total));
+    }
+    // And then we build the output
+    let breakdown = expression.roll_with_breakdown(&mut rng)?;
+    let reading = options.system.as_ref().and_then(|x| x.interpret(&breakdown));
+    if options.short_output {
+        output += &breakdown.total.to_string();
     }
     else {
-        running_total = ast.compute_with_output(&mut output, &mut rng, skip_dropped, colour)?;
+        breakdown.render(&mut output, options.skip_dropped, options.palette.as_ref(), options.tag_crits);
+        if output.len() > limits.max_output_length {
+            return Err(LimitError::OutputLength(limits.max_output_length).into());
+        }
+        // Specify what the result of our rolls were
+        output += &(" => ".to_owned() + &breakdown.total.to_string());
     }
-    // Specify what the result of our rolls were
-    output += &(" => ".to_owned() + &running_total.to_string());
-    if short_output {
-        return Ok(running_total.to_string());
+    if let Some(x) = reading {
+        output += &format!(" ({})", x);
+    }
+    Ok((output, breakdown.total))
+}
+
+// Rolls an attack, giving its output and the damage dealt,
+// i.e. 12 + 7 => 19 vs 15: HIT; damage (3 + 5) + 4 => 12
+fn attack_to_output(input: &str, help_message: &String, options: &OutputOptions, echo: bool, limits: &Limits) -> Result<(String, i64), Error> {
+    let attack = Attack::from_input(input, help_message, limits)?;
+    let result = match &options.system {
+        Some(x) => attack.roll_under(x.as_ref(), &mut rand::rng())?,
+        None => attack.roll(&mut rand::rng())?
+    };
+    // Systems with degrees of success show the degree rather than HIT or MISS
+    let outcome = result.degree.map_or(result.outcome.to_string(), |x| x.to_string());
+    let mut output: String = String::new();
+    if echo {
+        output += &(attack.to_string() + ": ");
+    }
+    if options.short_output {
+        output += &outcome;
+        if let Some(x) = &result.damage {
+            output += &format!(", {}", x.total);
+        }
+        return Ok((output, result.damage_total()));
+    }
+    result.to_hit.render(&mut output, options.skip_dropped, options.palette.as_ref(), options.tag_crits);
+    output += &format!(" => {} vs {}: {}", result.to_hit.total, attack.target, outcome);
+    if let Some(x) = &result.damage {
+        output += "; damage ";
+        x.render(&mut output, options.skip_dropped, options.palette.as_ref(), options.tag_crits);
+        output += &format!(" => {}", x.total);
+    }
+    if output.len() > limits.max_output_length {
+        return Err(LimitError::OutputLength(limits.max_output_length).into());
+    }
+    Ok((output, result.damage_total()))
+}
+
+// Rolls a pool of narrative dice, giving its output and its net successes (or failures, as
+// a negative number), i.e. ability (1 success) + difficulty (1 threat) => 1 success, 1 threat
+fn narrative_to_output(input: &str, options: &OutputOptions, echo: bool, limits: &Limits) -> Result<(String, i64), Error> {
+    let pool = NarrativePool::parse_with_limits(input, limits)?;
+    let result = pool.roll(&mut rand::rng());
+    let mut output: String = String::new();
+    if echo {
+        output += &(pool.to_string() + ": ");
+    }
+    if options.short_output {
+        output += &result.net.to_string();
     }
     else {
-        return Ok(output);
+        result.render(&mut output, options.palette.as_ref());
+    }
+    if output.len() > limits.max_output_length {
+        return Err(LimitError::OutputLength(limits.max_output_length).into());
     }
+    // Force dice alone are not a check, so neither succeed nor fail
+    if result.dice.iter().any(|x| x.die != NarrativeDie::Force) {
+        output += if result.is_success() { " (success)" } else { " (failure)" };
+    }
+    Ok((output, result.net_successes()))
 }
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 // No error output in WASM
 pub fn consume_input_to_output_without_error(input: String, help_message: String, skip_dropped: bool, short_output: bool) -> String {
-    let output = consume_input_to_output(input.to_lowercase(), &help_message, skip_dropped, short_output, false);
+    let options = OutputOptions { skip_dropped, short_output, ..OutputOptions::default() };
+    let output = consume_input_to_output(input.to_lowercase(), &help_message, &options, &Limits::default());
     match output {
-        Ok(x) => {
-            return x;
-        }
-        Err(y) => {
-            return y.to_string();
-        }
+        Ok(x) => x,
+        Err(y) => y.to_string()
     }
 }
@@ -0,0 +1,70 @@
+// Bounds on how much work a single expression may ask for, so that untrusted input
+// (i.e. from a chat bot) cannot exhaust memory or the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // The most dice a single roll such as 4d6 may contain
+    pub max_dice_per_roll: usize,
+    // The most dice the whole expression may roll, summed over every roll (and over every
+    // table, for a roll on a random table)
+    pub max_total_dice: usize,
+    // How deeply brackets, or random tables rolling on each other, may be nested
+    pub max_nesting_depth: usize,
+    // The length of the expression in characters, including whitespace
+    pub max_expression_length: usize,
+    // The length of the rendered breakdown in bytes
+    pub max_output_length: usize
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_dice_per_roll: 10_000,
+            max_total_dice: 100_000,
+            max_nesting_depth: 32,
+            max_expression_length: 1_000,
+            max_output_length: 100_000
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // No limits at all, for trusted input
+    pub fn unlimited() -> Self {
+        Limits {
+            max_dice_per_roll: usize::MAX,
+            max_total_dice: usize::MAX,
+            max_nesting_depth: usize::MAX,
+            max_expression_length: usize::MAX,
+            max_output_length: usize::MAX
+        }
+    }
+
+    pub fn max_dice_per_roll(mut self, max_dice_per_roll: usize) -> Self {
+        self.max_dice_per_roll = max_dice_per_roll;
+        self
+    }
+
+    pub fn max_total_dice(mut self, max_total_dice: usize) -> Self {
+        self.max_total_dice = max_total_dice;
+        self
+    }
+
+    pub fn max_nesting_depth(mut self, max_nesting_depth: usize) -> Self {
+        self.max_nesting_depth = max_nesting_depth;
+        self
+    }
+
+    pub fn max_expression_length(mut self, max_expression_length: usize) -> Self {
+        self.max_expression_length = max_expression_length;
+        self
+    }
+
+    pub fn max_output_length(mut self, max_output_length: usize) -> Self {
+        self.max_output_length = max_output_length;
+        self
+    }
+}
@@ -1,26 +1,333 @@
-use clap::{command, Arg, ArgAction};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
 use roll::*;
 
+// Whether to colour the output, given the --color choice. Under auto, NO_COLOR turns
+// colour off and CLICOLOR_FORCE turns it on even when not writing to a terminal
+fn use_colour(choice: &str) -> bool {
+    match choice {
+        "always" => true,
+        "never" => false,
+        _ => {
+            if std::env::var_os("NO_COLOR").is_some_and(|x| !x.is_empty()) {
+                return false;
+            }
+            if std::env::var_os("CLICOLOR_FORCE").is_some_and(|x| !x.is_empty() && x != "0") {
+                return true;
+            }
+            std::io::stdout().is_terminal()
+        }
+    }
+}
+
+// The contents of a file, or of standard input for -, exiting if it cannot be read
+fn read_source(source: &str) -> String {
+    let mut contents = String::new();
+    let result = if source == "-" {
+        std::io::stdin().read_to_string(&mut contents).map(|_| ())
+    }
+    else {
+        std::fs::read_to_string(source).map(|x| contents = x)
+    };
+    if let Err(error) = result {
+        eprintln!("Error: Could not read {}: {}", source, error);
+        std::process::exit(1);
+    }
+    contents
+}
+
+// Rolls initiative for every name=expression, given as arguments or one per line of a file,
+// and prints the order. In interactive mode, commands from standard input then advance turns
+fn run_initiative(matches: &ArgMatches, limits: &Limits) {
+    let lines: Vec<String> = match matches.get_one::<String>("file") {
+        Some(x) => read_source(x).lines().map(str::to_owned).collect(),
+        None => matches.get_many::<String>("entries").map(|x| x.cloned().collect()).unwrap_or_default()
+    };
+    let mut combatants = Vec::new();
+    for (line_index, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match Combatant::parse_with_limits(line, limits) {
+            Ok(x) => combatants.push(x),
+            Err(error) => {
+                eprintln!("Error on entry {}: {}", line_index + 1, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    let tie_break = match matches.get_one::<String>("tie").map(String::as_str) {
+        Some("reroll") => TieBreak::Reroll,
+        _ => TieBreak::Modifier
+    };
+    let mut initiative = match Initiative::roll(&combatants, tie_break, &mut rand::rng()) {
+        Ok(x) => x,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let json = matches.get_flag("json");
+    let show = |initiative: &Initiative| {
+        if json {
+            print_json(initiative);
+        }
+        else {
+            println!("{}", initiative);
+        }
+    };
+    show(&initiative);
+    if !matches.get_flag("interactive") {
+        return;
+    }
+    let prompt = std::io::stdin().is_terminal();
+    let mut stdin = std::io::stdin().lock();
+    loop {
+        if prompt {
+            print!("initiative> ");
+            let _ = std::io::stdout().flush();
+        }
+        let mut command = String::new();
+        match stdin.read_line(&mut command) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        match command.trim() {
+            // Pressing enter alone moves on too
+            "" | "next" | "n" => {
+                initiative.advance();
+                if json {
+                    print_json(&initiative);
+                }
+                else {
+                    println!("Round {}: {}'s turn", initiative.round, initiative.current().name);
+                }
+            }
+            "order" | "o" => show(&initiative),
+            "quit" | "q" | "exit" => return,
+            x => eprintln!("Unknown command: {} (expected next, order or quit)", x)
+        }
+    }
+}
+
+// Prints the initiative order as JSON, on a single line
+fn print_json(initiative: &Initiative) {
+    match serde_json::to_string(initiative) {
+        Ok(x) => println!("{}", x),
+        Err(error) => eprintln!("Error: {}", error)
+    }
+}
+
+// Rolls on a table the given number of times, printing each entry
+fn run_table(matches: &ArgMatches, limits: &Limits) {
+    let path = matches.get_one::<String>("table").unwrap();
+    let table = match Table::load_with_limits(path, limits) {
+        Ok(x) => x,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let mut rng = rand::rng();
+    for _ in 0..*matches.get_one::<usize>("times").unwrap() {
+        match table.roll(&mut rng) {
+            Ok(x) => println!("{}", x),
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// When a file was last changed, if that can be told
+fn modified(path: &str) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+// Rolls each line typed until quit, reloading the sheet whenever its file changes (or on
+// reload). A sheet which no longer loads is reported, and the last good one kept
+fn run_interactive(options: &mut OutputOptions, sheet_path: Option<&str>, limits: &Limits) {
+    let prompt = std::io::stdin().is_terminal();
+    let mut stdin = std::io::stdin().lock();
+    let mut last_modified = sheet_path.and_then(modified);
+    loop {
+        if prompt {
+            print!("roll> ");
+            let _ = std::io::stdout().flush();
+        }
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "quit" | "q" | "exit" => return,
+            _ => {}
+        }
+        if let Some(path) = sheet_path {
+            let now_modified = modified(path);
+            if line == "reload" || now_modified != last_modified {
+                last_modified = now_modified;
+                match Sheet::load(path) {
+                    Ok(x) => {
+                        options.sheet = Some(x);
+                        eprintln!("Reloaded {}", path);
+                    }
+                    // Errors reading the file already name it
+                    Err(error @ Error::File(..)) => eprintln!("Error: {}", error),
+                    Err(error) => eprintln!("Error: Could not reload the sheet {}: {}", path, error)
+                }
+            }
+        }
+        if line == "reload" {
+            continue;
+        }
+        match consume_input_to_output(line.to_owned(), &String::new(), options, limits) {
+            Ok(output) => println!("{}", output),
+            Err(error) => eprintln!("Error: {}", error)
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let default_limits = Limits::default();
     let mut command = command!()
-        .arg(Arg::new("dice").num_args(1..).help("The dice to be rolled - syntax is NdN, and many dice may be summed or subtracted.\nIt is also possible to drop the N lowest or highest results, i.e. with 2d20dl1 or 2d20dh1. The dropped dice will be marked in \x1b[0;91mred\x1b[0m"))
+        .arg(Arg::new("dice").num_args(1..).help("The dice to be rolled - syntax is NdN, and many dice may be summed or subtracted.\nIt is also possible to drop the N lowest or highest results, i.e. with 2d20dl1 or 2d20dh1, or keep them with 3d20kh1. The dropped dice will be marked with an X.\nAdvantage and disadvantage may be written adv and dis, or d20a and d20d.\nForged in the Dark pools may be written fitd(3), which keeps the highest of 3d6 (or the lowest of 2d6 for fitd(0)).\nAttacks may be rolled against a target, with damage rolled only on a hit, i.e. d20+7 vs 15 => 2d6+4\nCrits and fumbles are the highest and lowest faces, unless given i.e. with d20cs>=19 or d20cf<=2"))
+        .arg(Arg::new("file").short('f').long("file").conflicts_with("dice").help("Roll each line of a file as its own expression, or of standard input if the file is -.\nPassing - in place of the dice also reads standard input"))
         .arg(Arg::new("skip-dropped").short('s').long("skip-dropped").action(ArgAction::SetTrue).help("Do not show dice which have been dropped i.e. in 2d20dl1"))
         .arg(Arg::new("short-output").long("short-output").action(ArgAction::SetTrue).help("Only show the result of the rolls"))
+        .arg(Arg::new("color").long("color").visible_alias("colour").value_parser(["auto", "always", "never"]).default_value("auto").help("When to colour the output. auto colours only when writing to a terminal, and respects NO_COLOR and CLICOLOR_FORCE.\nColours may be changed with ROLL_COLORS, i.e. ROLL_COLORS='dropped=0;31:crit=1;32:fumble=1;33'"))
+        .arg(Arg::new("tag-crits").short('t').long("tag-crits").action(ArgAction::SetTrue).help("Follow critical successes and fumbles with CRIT or FUMBLE"))
+        .arg(Arg::new("separate").short('m').long("separate").action(ArgAction::SetTrue).help("Roll each argument as its own expression, as if they were separated by commas"))
+        .arg(Arg::new("total").short('T').long("total").action(ArgAction::SetTrue).help("When rolling several expressions, also show the sum of their totals"))
+        .arg(Arg::new("system").long("system").value_parser(SYSTEM_NAMES.to_vec()).help("Understand the shorthands of a game system, and show what each result means under its rules.\n5e: stat is 4d6kh3. pf2e: flat is d20, and checks against a DC (d20+7 vs 15) give a degree of success. pbta: move is 2d6, and totals read as miss, partial or hit. fitd: fitd(n) rolls a pool of n d6s (2d6 keeping the lowest for 0), read by its highest die. yz: counts the 6s rolled"))
+        .arg(Arg::new("narrative").short('n').long("narrative").action(ArgAction::SetTrue).help("Roll pools of Genesys narrative dice, such as 2g 1y 2p, cancelling opposing symbols.\nDice are written by colour or name: b boost, k setback, g ability, p difficulty, y proficiency, r challenge and w force"))
+        .arg(Arg::new("sheet").long("sheet").help("A character sheet (TOML, or JSON if it ends in .json) whose names may be used in expressions, i.e. d20 + athletics.\nValues are whole numbers or expressions using other names, i.e. athletics = \"proficiency + str_mod\""))
+        .arg(Arg::new("interactive").short('i').long("interactive").action(ArgAction::SetTrue).conflicts_with_all(["dice", "file"]).help("Roll expressions as they are typed, one per line, until quit. Any sheet is reloaded whenever it changes"))
+        .arg(Arg::new("echo").short('e').long("echo").action(ArgAction::SetTrue).help("Show the expression, as it was understood, before its result"))
+        .arg(Arg::new("max-dice").long("max-dice").value_parser(value_parser!(usize)).help(format!("The most dice a single roll may contain [default: {}]", default_limits.max_dice_per_roll)))
+        .arg(Arg::new("max-total-dice").long("max-total-dice").value_parser(value_parser!(usize)).help(format!("The most dice the whole expression may roll [default: {}]", default_limits.max_total_dice)))
+        .arg(Arg::new("max-depth").long("max-depth").value_parser(value_parser!(usize)).help(format!("How deeply brackets may be nested [default: {}]", default_limits.max_nesting_depth)))
+        .arg(Arg::new("max-length").long("max-length").value_parser(value_parser!(usize)).help(format!("The longest expression, in characters, which will be rolled [default: {}]", default_limits.max_expression_length)))
+        .arg(Arg::new("max-output").long("max-output").value_parser(value_parser!(usize)).help(format!("The longest output, in bytes, which will be printed [default: {}]", default_limits.max_output_length)))
+        .subcommand(Command::new("initiative")
+            .about("Roll initiative for each creature and print the order they act in")
+            .arg(Arg::new("entries").num_args(1..).help("Each creature as name=expression, i.e. goblin=d20+2 Aria=d20+5"))
+            .arg(Arg::new("file").short('f').long("file").conflicts_with("entries").help("Read the creatures from a file, one name=expression per line, or from standard input if the file is -"))
+            .arg(Arg::new("tie").long("tie").value_parser(["modifier", "reroll"]).default_value("modifier").help("How to order tied creatures: by the higher modifier (re-rolling any still tied), or by re-rolling"))
+            .arg(Arg::new("json").long("json").action(ArgAction::SetTrue).help("Print the order as JSON"))
+            .arg(Arg::new("interactive").short('i').long("interactive").action(ArgAction::SetTrue).help("Afterwards, read commands from standard input: next (or a blank line) moves to the next turn, order prints the order and quit stops")))
+        .subcommand(Command::new("table")
+            .about("Roll on a random table and print the entry")
+            .arg(Arg::new("table").required(true).help("The table, as .csv, .toml or .yaml, with a range such as 01-15 for each entry.\nEntries may roll dice with {2d6}, roll on another table with [[treasure.csv]], or be reroll"))
+            .arg(Arg::new("times").short('n').long("times").value_parser(value_parser!(usize)).default_value("1").help("How many times to roll on the table")))
         .long_about("Rolls dice for use in D&D");
     command.build();
     let help_message = command.render_long_help().to_string();
     let matches = command.get_matches();
     // Use a finite state machine approach to consume the input
     let input;
+    let mut batch_source = matches.get_one::<String>("file").cloned();
     if let Some(matches_found) = matches.get_many::<String>("dice") {
-        input = matches_found.fold(String::new(), |v, x| v + " " + x);
+        // Several expressions may be given, separated by commas or as separate arguments
+        let separator = if matches.get_flag("separate") { ", " } else { " " };
+        input = matches_found.cloned().collect::<Vec<String>>().join(separator);
+        if input == "-" {
+            batch_source = Some(input.clone());
+        }
+    }
+    else if batch_source.is_some() || matches.subcommand().is_some() || matches.get_flag("interactive") {
+        input = String::new();
     }
     else {
         println!("{}", help_message);
         return Ok(());
     }
-    let skip_dropped = matches.get_flag("skip-dropped");
-    let short_output = matches.get_flag("short-output");
-    println!("{}", consume_input_to_output(input, &help_message, skip_dropped, short_output, true)?);
+    let mut options = OutputOptions {
+        skip_dropped: matches.get_flag("skip-dropped"),
+        short_output: matches.get_flag("short-output"),
+        palette: if use_colour(matches.get_one::<String>("color").unwrap()) {
+            Some(std::env::var("ROLL_COLORS").map(|x| Palette::from_spec(&x)).unwrap_or_default())
+        }
+        else {
+            None
+        },
+        tag_crits: matches.get_flag("tag-crits"),
+        echo: matches.get_flag("echo"),
+        grand_total: matches.get_flag("total"),
+        system: matches.get_one::<String>("system").and_then(|x| system_by_name(x)),
+        narrative: matches.get_flag("narrative"),
+        sheet: None
+    };
+    let sheet_path = matches.get_one::<String>("sheet");
+    if let Some(path) = sheet_path {
+        match Sheet::load(path) {
+            Ok(x) => options.sheet = Some(x),
+            Err(error) => {
+                match error {
+                    Error::File(..) => eprintln!("Error: {}", error),
+                    _ => eprintln!("Error: Could not load the sheet {}: {}", path, error)
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+    let limits = default_limits
+        .max_dice_per_roll(matches.get_one::<usize>("max-dice").copied().unwrap_or(default_limits.max_dice_per_roll))
+        .max_total_dice(matches.get_one::<usize>("max-total-dice").copied().unwrap_or(default_limits.max_total_dice))
+        .max_nesting_depth(matches.get_one::<usize>("max-depth").copied().unwrap_or(default_limits.max_nesting_depth))
+        .max_expression_length(matches.get_one::<usize>("max-length").copied().unwrap_or(default_limits.max_expression_length))
+        .max_output_length(matches.get_one::<usize>("max-output").copied().unwrap_or(default_limits.max_output_length));
+    if let Some(x) = matches.subcommand_matches("initiative") {
+        run_initiative(x, &limits);
+        return Ok(());
+    }
+    if let Some(x) = matches.subcommand_matches("table") {
+        run_table(x, &limits);
+        return Ok(());
+    }
+    if matches.get_flag("interactive") {
+        run_interactive(&mut options, sheet_path.map(String::as_str), &limits);
+        return Ok(());
+    }
+    // In batch mode every line is rolled in turn, and a bad line is reported without
+    // stopping the rest
+    if let Some(source) = batch_source {
+        let contents = read_source(&source);
+        let mut any_failed = false;
+        for (line_index, line) in contents.lines().enumerate() {
+            // A comment alone on a line is a note, not an expression
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match consume_input_to_output(line.to_owned(), &String::new(), &options, &limits) {
+                Ok(output) => {
+                    println!("{}", output);
+                }
+                Err(error) => {
+                    eprintln!("Error on line {}: {}", line_index + 1, error);
+                    any_failed = true;
+                }
+            }
+        }
+        if any_failed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    match consume_input_to_output(input, &help_message, &options, &limits) {
+        Ok(output) => {
+            println!("{}", output);
+        }
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use rand::{Rng, RngExt};
+use crate::error::{Error, LimitError, ParseError};
+use crate::limits::Limits;
+use crate::palette::Palette;
+
+/// A symbol which may appear on a narrative die, as in Genesys and Star Wars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Symbol {
+    Success,
+    Failure,
+    Advantage,
+    Threat,
+    /// Counts as a success as well, but is never cancelled
+    Triumph,
+    /// Counts as a failure as well, but is never cancelled
+    Despair,
+    /// Force points, which are never cancelled
+    LightSide,
+    DarkSide
+}
+
+/// The kinds of narrative die, each with its own faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NarrativeDie {
+    /// Blue d6
+    Boost,
+    /// Black d6
+    Setback,
+    /// Green d8
+    Ability,
+    /// Purple d8
+    Difficulty,
+    /// Yellow d12
+    Proficiency,
+    /// Red d12
+    Challenge,
+    /// White d12
+    Force
+}
+
+/// A multiset of symbols, such as a single face or the total of a pool.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct SymbolSet {
+    counts: BTreeMap<Symbol, u32>
+}
+
+/// A pool of narrative dice, such as `2g 1y 2p` (two ability, one proficiency and two
+/// difficulty dice).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarrativePool {
+    dice: Vec<NarrativeDie>
+}
+
+/// A single narrative die as it was rolled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NarrativeDieResult {
+    pub die: NarrativeDie,
+    pub face: SymbolSet
+}
+
+/// A rolled pool: every die, and the symbols left once opposing ones have cancelled out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NarrativeResult {
+    pub dice: Vec<NarrativeDieResult>,
+    pub net: SymbolSet
+}
+
+use Symbol::*;
+
+// The faces of each die, following the Genesys core rulebook. An empty face is blank
+const BOOST_FACES: &[&[Symbol]] = &[&[], &[], &[Success], &[Success, Advantage], &[Advantage, Advantage], &[Advantage]];
+const SETBACK_FACES: &[&[Symbol]] = &[&[], &[], &[Failure], &[Failure], &[Threat], &[Threat]];
+const ABILITY_FACES: &[&[Symbol]] = &[&[], &[Success], &[Success], &[Success, Success], &[Advantage], &[Advantage],
+    &[Success, Advantage], &[Advantage, Advantage]];
+const DIFFICULTY_FACES: &[&[Symbol]] = &[&[], &[Failure], &[Failure, Failure], &[Threat], &[Threat], &[Threat],
+    &[Threat, Threat], &[Failure, Threat]];
+const PROFICIENCY_FACES: &[&[Symbol]] = &[&[], &[Success], &[Success], &[Success, Success], &[Success, Success],
+    &[Advantage], &[Success, Advantage], &[Success, Advantage], &[Success, Advantage], &[Advantage, Advantage],
+    &[Advantage, Advantage], &[Triumph]];
+const CHALLENGE_FACES: &[&[Symbol]] = &[&[], &[Failure], &[Failure], &[Failure, Failure], &[Failure, Failure],
+    &[Threat], &[Threat], &[Failure, Threat], &[Failure, Threat], &[Threat, Threat], &[Threat, Threat], &[Despair]];
+const FORCE_FACES: &[&[Symbol]] = &[&[DarkSide], &[DarkSide], &[DarkSide], &[DarkSide], &[DarkSide], &[DarkSide],
+    &[DarkSide, DarkSide], &[LightSide], &[LightSide], &[LightSide, LightSide], &[LightSide, LightSide],
+    &[LightSide, LightSide]];
+
+impl Symbol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Advantage => "advantage",
+            Self::Threat => "threat",
+            Self::Triumph => "triumph",
+            Self::Despair => "despair",
+            Self::LightSide => "light side",
+            Self::DarkSide => "dark side"
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl NarrativeDie {
+    /// Every face of the die, in no particular order.
+    pub fn faces(&self) -> &'static [&'static [Symbol]] {
+        match self {
+            Self::Boost => BOOST_FACES,
+            Self::Setback => SETBACK_FACES,
+            Self::Ability => ABILITY_FACES,
+            Self::Difficulty => DIFFICULTY_FACES,
+            Self::Proficiency => PROFICIENCY_FACES,
+            Self::Challenge => CHALLENGE_FACES,
+            Self::Force => FORCE_FACES
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Boost => "boost",
+            Self::Setback => "setback",
+            Self::Ability => "ability",
+            Self::Difficulty => "difficulty",
+            Self::Proficiency => "proficiency",
+            Self::Challenge => "challenge",
+            Self::Force => "force"
+        }
+    }
+
+    // The letter for the die's colour (with k for black), or its full name
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "b" | "boost" => Some(Self::Boost),
+            "k" | "setback" => Some(Self::Setback),
+            "g" | "ability" => Some(Self::Ability),
+            "p" | "difficulty" => Some(Self::Difficulty),
+            "y" | "proficiency" => Some(Self::Proficiency),
+            "r" | "challenge" => Some(Self::Challenge),
+            "w" | "force" => Some(Self::Force),
+            _ => None
+        }
+    }
+
+    // The letter used for the die in a pool
+    fn letter(&self) -> char {
+        match self {
+            Self::Boost => 'b',
+            Self::Setback => 'k',
+            Self::Ability => 'g',
+            Self::Difficulty => 'p',
+            Self::Proficiency => 'y',
+            Self::Challenge => 'r',
+            Self::Force => 'w'
+        }
+    }
+}
+
+impl SymbolSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_symbols(symbols: &[Symbol]) -> Self {
+        let mut set = Self::new();
+        for symbol in symbols {
+            set.add(*symbol, 1);
+        }
+        set
+    }
+
+    pub fn add(&mut self, symbol: Symbol, count: u32) {
+        if count > 0 {
+            *self.counts.entry(symbol).or_insert(0) += count;
+        }
+    }
+
+    /// How many of the symbol there are, which is 0 if there are none.
+    pub fn count(&self, symbol: Symbol) -> u32 {
+        self.counts.get(&symbol).copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Every symbol with how many there are, in a fixed order.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, u32)> + '_ {
+        self.counts.iter().map(|(symbol, count)| (*symbol, *count))
+    }
+
+    /// Cancels out opposing symbols: successes against failures, and advantages against
+    /// threats. Triumphs and despairs count towards the successes and failures they cancel,
+    /// but are kept themselves, as are any Force points.
+    ///
+    /// ```
+    /// use roll::{Symbol, SymbolSet};
+    /// use roll::Symbol::*;
+    ///
+    /// let net = |symbols: &[Symbol]| SymbolSet::from_symbols(symbols).cancelled();
+    /// assert_eq!(net(&[Success, Success, Failure, Threat]), SymbolSet::from_symbols(&[Success, Threat]));
+    /// assert_eq!(net(&[Success, Failure, Failure, Advantage, Advantage]), SymbolSet::from_symbols(&[Failure, Advantage, Advantage]));
+    /// // An even split leaves nothing
+    /// assert!(net(&[Success, Failure, Advantage, Threat]).is_empty());
+    /// // A triumph is a success which stays, whether or not it cancels a failure
+    /// assert_eq!(net(&[Triumph, Failure]), SymbolSet::from_symbols(&[Triumph]));
+    /// assert_eq!(net(&[Triumph, Success]), SymbolSet::from_symbols(&[Success, Success, Triumph]));
+    /// // And a despair is a failure which stays
+    /// assert_eq!(net(&[Despair, Success]), SymbolSet::from_symbols(&[Despair]));
+    /// assert_eq!(net(&[Despair, Triumph]), SymbolSet::from_symbols(&[Triumph, Despair]));
+    /// assert_eq!(net(&[Despair]), SymbolSet::from_symbols(&[Failure, Despair]));
+    /// // Force points never cancel, each other or anything else
+    /// assert_eq!(net(&[LightSide, DarkSide, DarkSide, Success]), SymbolSet::from_symbols(&[Success, LightSide, DarkSide, DarkSide]));
+    /// ```
+    pub fn cancelled(&self) -> Self {
+        let successes = self.count(Success) + self.count(Triumph);
+        let failures = self.count(Failure) + self.count(Despair);
+        let mut net = Self::new();
+        if successes > failures {
+            net.add(Success, successes - failures);
+        }
+        else {
+            net.add(Failure, failures - successes);
+        }
+        let advantages = self.count(Advantage);
+        let threats = self.count(Threat);
+        if advantages > threats {
+            net.add(Advantage, advantages - threats);
+        }
+        else {
+            net.add(Threat, threats - advantages);
+        }
+        for symbol in [Triumph, Despair, LightSide, DarkSide] {
+            net.add(symbol, self.count(symbol));
+        }
+        net
+    }
+
+    // i.e. 2 success, 1 triumph, painting triumphs and despairs from the palette
+    fn render(&self, output: &mut String, palette: Option<&Palette>) {
+        if self.is_empty() {
+            *output += "blank";
+            return;
+        }
+        for (index, (symbol, count)) in self.iter().enumerate() {
+            if index > 0 {
+                *output += ", ";
+            }
+            let text = format!("{} {}", count, symbol);
+            match palette {
+                Some(x) if symbol == Triumph => Palette::paint(output, &x.crit, &text),
+                Some(x) if symbol == Despair => Palette::paint(output, &x.fumble, &text),
+                _ => *output += &text
+            }
+        }
+    }
+}
+
+impl fmt::Display for SymbolSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut output = String::new();
+        self.render(&mut output, None);
+        write!(f, "{}", output)
+    }
+}
+
+impl NarrativePool {
+    /// Parses a pool, applying the default [`Limits`]. Each die is written as a count and
+    /// then its colour (`b`oost, blac`k` setback, `g`reen ability, `p`urple difficulty,
+    /// `y`ellow proficiency, `r`ed challenge and `w`hite force) or name, separated by spaces
+    /// or `+`.
+    ///
+    /// ```
+    /// use roll::{NarrativePool, Symbol};
+    ///
+    /// let pool = NarrativePool::parse("2g + 1y + 2 difficulty").unwrap();
+    /// assert_eq!(pool.to_string(), "2g 1y 2p");
+    ///
+    /// let result = pool.roll(&mut rand::rng());
+    /// assert_eq!(result.dice.len(), 5);
+    /// assert!(result.net.count(Symbol::Success) == 0 || result.net.count(Symbol::Failure) == 0);
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        Self::parse_with_limits(input, &Limits::default())
+    }
+
+    pub fn parse_with_limits(input: &str, limits: &Limits) -> Result<Self, Error> {
+        if input.chars().count() > limits.max_expression_length {
+            return Err(LimitError::ExpressionLength(limits.max_expression_length).into());
+        }
+        let mut dice = Vec::new();
+        // A count on its own applies to the name which follows, as in 2 difficulty
+        let mut pending_count: Option<usize> = None;
+        for term in input.split(|x: char| x.is_whitespace() || x == '+').filter(|x| !x.is_empty()) {
+            let name_start = term.find(|x: char| !x.is_ascii_digit()).unwrap_or(term.len());
+            let given = match &term[..name_start] {
+                "" => None,
+                x => Some(x.parse::<usize>().map_err(|_| LimitError::DicePerRoll(limits.max_dice_per_roll))?)
+            };
+            if name_start == term.len() {
+                pending_count = given;
+                continue;
+            }
+            let count = given.or(pending_count.take()).unwrap_or(1);
+            let Some(die) = NarrativeDie::from_name(&term[name_start..].to_ascii_lowercase()) else {
+                return Err(ParseError::new(format!("Unknown narrative die: {}", term), None).into());
+            };
+            if count > limits.max_dice_per_roll {
+                return Err(LimitError::DicePerRoll(limits.max_dice_per_roll).into());
+            }
+            dice.extend(std::iter::repeat_n(die, count));
+            if dice.len() > limits.max_total_dice {
+                return Err(LimitError::TotalDice(limits.max_total_dice).into());
+            }
+        }
+        if pending_count.is_some() {
+            return Err(ParseError::new("Expected a narrative die after the count", None).into());
+        }
+        if dice.is_empty() {
+            return Err(ParseError::new("Expected some narrative dice, i.e. 2g 1p", None).into());
+        }
+        Ok(NarrativePool { dice })
+    }
+
+    pub fn dice(&self) -> &[NarrativeDie] {
+        &self.dice
+    }
+
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> NarrativeResult {
+        let mut rolled = SymbolSet::new();
+        let mut dice = Vec::with_capacity(self.dice.len());
+        for die in &self.dice {
+            let faces = die.faces();
+            let face = SymbolSet::from_symbols(faces[rng.random_range(0..faces.len())]);
+            for (symbol, count) in face.iter() {
+                rolled.add(symbol, count);
+            }
+            dice.push(NarrativeDieResult { die: *die, face });
+        }
+        NarrativeResult { dice, net: rolled.cancelled() }
+    }
+}
+
+impl fmt::Display for NarrativePool {
+    // Grouping runs of the same die, i.e. 2g 1y 2p
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut index = 0;
+        while index < self.dice.len() {
+            let die = self.dice[index];
+            let run = self.dice[index..].iter().take_while(|x| **x == die).count();
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}{}", run, die.letter())?;
+            index += run;
+        }
+        Ok(())
+    }
+}
+
+impl NarrativeResult {
+    /// Whether the check succeeded, which needs at least one success left after cancelling.
+    pub fn is_success(&self) -> bool {
+        self.net.count(Success) > 0
+    }
+
+    /// The successes left after cancelling, or the failures as a negative number.
+    pub fn net_successes(&self) -> i64 {
+        i64::from(self.net.count(Success)) - i64::from(self.net.count(Failure))
+    }
+
+    /// Writes out every die and what is left once they cancel, i.e.
+    /// `ability (1 success) + difficulty (1 threat) => 1 success, 1 threat`. Triumphs and
+    /// despairs are coloured as crits and fumbles, if a palette is given.
+    pub fn render(&self, output: &mut String, palette: Option<&Palette>) {
+        for (index, result) in self.dice.iter().enumerate() {
+            if index > 0 {
+                *output += " + ";
+            }
+            *output += &format!("{} (", result.die.name());
+            result.face.render(output, palette);
+            *output += ")";
+        }
+        *output += " => ";
+        self.net.render(output, palette);
+    }
+}
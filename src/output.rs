@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use crate::palette::Palette;
+use crate::system::System;
+use crate::sheet::Sheet;
+
+/// How the results of [`consume_input_to_output`](crate::consume_input_to_output) are
+/// written out.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Leave dropped dice out of the breakdown
+    pub skip_dropped: bool,
+    /// Only show the total of each expression
+    pub short_output: bool,
+    /// The colours to use, or none for plain text
+    pub palette: Option<Palette>,
+    /// Follow crits and fumbles with `CRIT` or `FUMBLE`
+    pub tag_crits: bool,
+    /// Show each expression, as it was understood, before its result
+    pub echo: bool,
+    /// When rolling several expressions, finish with the sum of their totals
+    pub grand_total: bool,
+    /// The game system whose shorthands are understood and whose reading of each result is
+    /// shown, if any
+    pub system: Option<Arc<dyn System>>,
+    /// Roll pools of narrative dice, such as `2g 1p`, rather than expressions
+    pub narrative: bool,
+    /// The character sheet whose names may be used in expressions, if any
+    pub sheet: Option<Sheet>
+}
+
+impl OutputOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
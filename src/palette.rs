@@ -0,0 +1,45 @@
+/// The colours used to highlight parts of a breakdown, each as the parameters of an ANSI
+/// SGR escape code (i.e. `0;91` for bright red).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub dropped: String,
+    pub crit: String,
+    pub fumble: String
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            dropped: "0;91".to_owned(),
+            crit: "1;92".to_owned(),
+            fumble: "1;93".to_owned()
+        }
+    }
+}
+
+impl Palette {
+    /// Overrides the default colours from a spec such as `dropped=0;31:crit=1;32`, in the
+    /// style of `GCC_COLORS`. Unknown names and malformed entries are ignored.
+    pub fn from_spec(spec: &str) -> Self {
+        let mut palette = Self::default();
+        for entry in spec.split(':') {
+            let Some((name, code)) = entry.split_once('=') else {continue};
+            // Only digits and semicolons can appear in an SGR code
+            if !code.chars().all(|x| x.is_ascii_digit() || x == ';') {
+                continue;
+            }
+            match name {
+                "dropped" => palette.dropped = code.to_owned(),
+                "crit" => palette.crit = code.to_owned(),
+                "fumble" => palette.fumble = code.to_owned(),
+                _ => {}
+            }
+        }
+        palette
+    }
+
+    // Wraps text in the given colour, resetting afterwards
+    pub(crate) fn paint(output: &mut String, code: &str, text: &str) {
+        *output += &format!("\x1b[{}m{}\x1b[0m", code, text);
+    }
+}
@@ -1,19 +1,32 @@
-use rand::{distr::Uniform, rngs::ThreadRng, RngExt};
+use std::fmt;
+use std::collections::BinaryHeap;
+use rand::{distr::Uniform, Rng, RngExt};
 use crate::tree::ASTInput;
+use crate::error::{ArithmeticError, Error, ParseError};
+use crate::breakdown::{Breakdown, BreakdownKind, DieResult};
+use crate::distribution::Distribution;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DropDie {
     DropLowest(usize),
-    DropHighest(usize)
+    DropHighest(usize),
+    // Keep only this many of the highest or lowest dice, dropping the rest
+    KeepHighest(usize),
+    KeepLowest(usize)
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum RollOrConstant {
     Roll(Roll),
     Const(ConstantRoll)
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Roll {
     // The number of dice to be rolled
     pub num_rolls: usize,
@@ -22,18 +35,90 @@ pub struct Roll {
     // Do we drop the highest, lowest, or no dice
     // (i.e. for advantage/disadvantage in DnD)
     pub drop_die: Option<DropDie>,
-    // The results of each individual roll
-    pub results: Vec<u32>
+    // Which faces count as a critical success or fumble (i.e. cs>=19), where none means
+    // the highest and lowest faces respectively
+    pub crit: Option<FaceComparison>,
+    pub fumble: Option<FaceComparison>,
+    // Whether this is a Forged in the Dark pool of zero dice (fitd(0)), which is rolled as
+    // 2d6 keeping the lowest and can never be a critical
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "std::ops::Not::not"))]
+    pub zero_dice: bool
 }
 
-#[derive(Clone, PartialEq, Debug)]
+/// A test against the face of a single die, as in `cs>=19` or `cf<=2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FaceComparison {
+    Equal(u32),
+    AtLeast(u32),
+    AtMost(u32)
+}
+
+impl FaceComparison {
+    // Builds a comparison from its notation, where a bare number means equality
+    fn from_notation(comparison: &str, value: u32) -> Option<Self> {
+        match comparison {
+            "" | "=" => Some(Self::Equal(value)),
+            ">=" => Some(Self::AtLeast(value)),
+            ">" => Some(Self::AtLeast(value.saturating_add(1))),
+            "<=" => Some(Self::AtMost(value)),
+            "<" => Some(Self::AtMost(value.saturating_sub(1))),
+            _ => None
+        }
+    }
+
+    pub fn matches(&self, face: u32) -> bool {
+        match self {
+            Self::Equal(x) => face == *x,
+            Self::AtLeast(x) => face >= *x,
+            Self::AtMost(x) => face <= *x
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstantRoll {
     pub constant_result: i64
 }
 
 impl Roll {
     pub fn new() -> Self {
-        Roll { num_rolls: 0, dice_sides: 0, drop_die: None, results: Vec::new() }
+        Roll { num_rolls: 0, dice_sides: 0, drop_die: None, crit: None, fumble: None, zero_dice: false }
+    }
+
+    /// A Forged in the Dark pool of zero dice, `fitd(0)`, which rolls 2d6 keeping the lowest.
+    pub fn zero_dice() -> Self {
+        Roll { num_rolls: 2, dice_sides: 6, drop_die: Some(DropDie::KeepLowest(1)), zero_dice: true, ..Self::new() }
+    }
+
+    /// Whether a face counts as a critical success, which by default is the highest face.
+    pub fn is_crit(&self, face: u32) -> bool {
+        match &self.crit {
+            Some(x) => x.matches(face),
+            // A one-sided die cannot be told apart from a fumble
+            None => self.dice_sides > 1 && face == u32::from(self.dice_sides)
+        }
+    }
+
+    /// Whether a face counts as a fumble, which by default is a 1.
+    pub fn is_fumble(&self, face: u32) -> bool {
+        match &self.fumble {
+            Some(x) => x.matches(face),
+            None => self.dice_sides > 1 && face == 1
+        }
+    }
+
+    // How many dice are dropped, and whether they are the highest rather than the lowest
+    pub(crate) fn dropped(&self) -> (usize, bool) {
+        match self.drop_die {
+            Some(DropDie::DropLowest(x)) => (x.min(self.num_rolls), false),
+            Some(DropDie::DropHighest(x)) => (x.min(self.num_rolls), true),
+            Some(DropDie::KeepHighest(x)) => (self.num_rolls - x.min(self.num_rolls), false),
+            Some(DropDie::KeepLowest(x)) => (self.num_rolls - x.min(self.num_rolls), true),
+            None => (0, false)
+        }
     }
 }
 
@@ -43,204 +128,257 @@ impl ConstantRoll {
     }
 }
 
+impl fmt::Display for DropDie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DropLowest(x) => write!(f, "dl{}", x),
+            Self::DropHighest(x) => write!(f, "dh{}", x),
+            Self::KeepHighest(x) => write!(f, "kh{}", x),
+            Self::KeepLowest(x) => write!(f, "kl{}", x)
+        }
+    }
+}
+
+impl fmt::Display for FaceComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equal(x) => write!(f, "={}", x),
+            Self::AtLeast(x) => write!(f, ">={}", x),
+            Self::AtMost(x) => write!(f, "<={}", x)
+        }
+    }
+}
+
+// i.e. 4d6dl1, leaving out the number of dice when there is only one
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.zero_dice {
+            return write!(f, "fitd(0)");
+        }
+        if self.num_rolls != 1 {
+            write!(f, "{}", self.num_rolls)?;
+        }
+        write!(f, "d{}", self.dice_sides)?;
+        if let Some(x) = &self.drop_die {
+            write!(f, "{}", x)?;
+        }
+        if let Some(x) = &self.crit {
+            write!(f, "cs{}", x)?;
+        }
+        if let Some(x) = &self.fumble {
+            write!(f, "cf{}", x)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConstantRoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.constant_result)
+    }
+}
+
+impl fmt::Display for RollOrConstant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Roll(x) => write!(f, "{}", x),
+            Self::Const(x) => write!(f, "{}", x)
+        }
+    }
+}
+
+// Sums the faces of a roll without wrapping, however many dice were rolled
+fn sum_results(results: &[DieResult]) -> Result<i64, ArithmeticError> {
+    results.iter().filter(|x| !x.dropped).try_fold(0i64, |total, result| total.checked_add(i64::from(result.face))).ok_or(ArithmeticError::Overflow)
+}
+
+// Rolling never modifies the roll itself, so a parsed expression may be rolled any number of times
 pub trait Rollable {
-    // Add to a running total and build up an output string
-    fn roll_with_output(&mut self, output: &mut String, rng: &mut ThreadRng, skip_dropped: bool, colour: bool) -> Result<i64, Box<dyn std::error::Error>>;
-    fn roll(&mut self, rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>>;
+    // Roll, recording every die so that the result can be explained afterwards
+    fn roll_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error>;
+    fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error>;
+    // The exact distribution of every possible result
+    fn distribution(&self) -> Result<Distribution, Error>;
 }
 
 impl Rollable for ConstantRoll {
-    fn roll_with_output(&mut self, output: &mut String, _rng: &mut ThreadRng, _skip_dropped: bool, _colour: bool) -> Result<i64, Box<dyn std::error::Error>> {
-        *output += &self.constant_result.to_string();
-        Ok(self.constant_result)
+    fn roll_with_breakdown<R: Rng + ?Sized>(&self, _rng: &mut R) -> Result<Breakdown, Error> {
+        Ok(Breakdown::new(self.constant_result, BreakdownKind::Constant))
     }
 
-    fn roll(&mut self, _rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>> {
+    fn roll<R: Rng + ?Sized>(&self, _rng: &mut R) -> Result<i64, Error> {
         Ok(self.constant_result)
     }
+
+    fn distribution(&self) -> Result<Distribution, Error> {
+        Ok(Distribution::constant(self.constant_result))
+    }
 }
 
 impl Rollable for Roll {
-    fn roll_with_output(&mut self, output: &mut String, rng: &mut ThreadRng, skip_dropped: bool, colour: bool) -> Result<i64, Box<dyn std::error::Error>> {
-        // Roll the dice
+    fn roll_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error> {
         if self.num_rolls == 0 {
-            return Ok(0);
+            return Ok(Breakdown::new(0, BreakdownKind::Roll(Vec::new())));
         }
-        self.results.reserve(self.num_rolls);
-        let distribution = Uniform::new_inclusive(1, self.dice_sides)?;
+        let distribution = Uniform::new_inclusive(1, self.dice_sides).map_err(|_| ParseError::new("Dice must have at least one side", None))?;
+        let mut results: Vec<DieResult> = Vec::with_capacity(self.num_rolls);
         for _ in 0..self.num_rolls {
-            self.results.push(rng.sample(distribution).into())
-        }
-        if self.num_rolls == 1 {
-            *output += &self.results.first().unwrap().to_string();
+            results.push(DieResult { face: rng.sample(distribution).into(), sides: self.dice_sides, dropped: false, crit: false, fumble: false });
         }
-        // If we are dropping, then...
-        match &self.drop_die {
-            Some(y) => {
-                let drop_n: usize;
-                match y {
-                    DropDie::DropLowest(x) => {
-                        if *x >= self.num_rolls {
-                            return Ok(0);
-                        }
-                        self.results.sort_by_key(|n| *n);
-                        drop_n = *x;
-                        
-                    }
-                    DropDie::DropHighest(x) => {
-                        if *x >= self.num_rolls {
-                            return Ok(0);
-                        }
-                        self.results.sort_by_key(|n| *n);
-                        self.results.reverse();
-                        drop_n = *x;
-                    }
-                }
-                // If skipping,
-                if skip_dropped {
-                    // Start by removing the dropped rolls
-                    self.results.drain(0..drop_n);
-                    // And then add to output string
-                    if self.results.len() > 1 {
-                        output.push('('); 
-                        for (result_index, result) in self.results.iter().enumerate() {
-                            if result_index > 0 {
-                                *output += " + "
-                            }
-                            *output += &result.to_string();
-                        }
-                        output.push(')');
-                    }
-                    else {
-                        for (result_index, result) in self.results.iter().enumerate() {
-                            if result_index > 0 {
-                                *output += " + "
-                            }
-                            *output += &result.to_string();
-                        }
-                    }
-                }
-                // If not skipping,
-                else {
-                    // Start with adding to output string
-                    if self.num_rolls > 1 && !skip_dropped {
-                        output.push('('); 
-                        for (result_index, result) in self.results.iter().enumerate() {
-                            if result_index > 0 {
-                                *output += " + "
-                            }
-                            if result_index < drop_n {
-                                if colour {
-                                    *output += "\x1b[0;91mX\x1b[0m";
-                                }
-                                else {
-                                    *output += "X";
-                                }
-                            }
-                            *output += &result.to_string();
-                        }
-                        output.push(')');
-                    }
-                    // And end with removing the dropped dice
-                    self.results.drain(0..drop_n);
-                }
+        // If we are dropping, sort so that the dropped dice come first and mark them
+        let (drop_n, drop_highest) = self.dropped();
+        if self.drop_die.is_some() {
+            results.sort_by_key(|x| x.face);
+            if drop_highest {
+                results.reverse();
             }
-            None => {
-                if self.num_rolls > 1 {
-                    output.push('('); 
-                    for (result_index, result) in self.results.iter().enumerate() {
-                        if result_index > 0 {
-                            *output += " + "
-                        }
-                        *output += &result.to_string();
-                    }
-                    output.push(')');
-                }
+            for result in results.iter_mut().take(drop_n) {
+                result.dropped = true;
             }
         }
-        Ok((self.results.iter().sum::<u32>() as i32) as i64)
+        // A dropped die does not count, so it can be neither a crit nor a fumble
+        for result in results.iter_mut().filter(|x| !x.dropped) {
+            result.crit = self.is_crit(result.face);
+            result.fumble = self.is_fumble(result.face);
+        }
+        let total = sum_results(&results)?;
+        if self.zero_dice {
+            return Ok(Breakdown::new(total, BreakdownKind::ZeroDice(results)));
+        }
+        Ok(Breakdown::new(total, BreakdownKind::Roll(results)))
     }
 
-    fn roll(&mut self, rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>> {
-        // Roll the dice
+    // Streams the dice into a running total rather than storing every face, so that
+    // huge pools such as 100000000d6 can be rolled when no breakdown is needed
+    fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error> {
         if self.num_rolls == 0 {
             return Ok(0);
         }
-        self.results.reserve(self.num_rolls);
-        let distribution = Uniform::new_inclusive(1, self.dice_sides)?;
+        let distribution = Uniform::new_inclusive(1, self.dice_sides).map_err(|_| ParseError::new("Dice must have at least one side", None))?;
+        let (drop_n, drop_highest) = self.dropped();
+        if drop_n >= self.num_rolls {
+            return Ok(0);
+        }
+        let keep_n = self.num_rolls - drop_n;
+        // Only remember whichever of the dropped or kept dice there are fewer of.
+        // Dropping the lowest dice is the same as keeping the highest, so ranking by
+        // the negated face lets one bounded heap serve both
+        let track_kept = keep_n < drop_n;
+        let mut tracked = SmallestN::new(if track_kept { keep_n } else { drop_n });
+        let rank_by_negated_face = drop_highest != track_kept;
+        let mut total: i64 = 0;
         for _ in 0..self.num_rolls {
-            self.results.push(rng.sample(distribution).into())
+            let result: u8 = rng.sample(distribution);
+            total = total.checked_add(result.into()).ok_or(ArithmeticError::Overflow)?;
+            tracked.push(if rank_by_negated_face { -i64::from(result) } else { i64::from(result) });
         }
-        // If we are dropping, then...
-        match &self.drop_die {
-            Some(y) => {
-                let drop_n: usize;
-                match y {
-                    DropDie::DropLowest(x) => {
-                        if *x >= self.num_rolls {
-                            return Ok(0);
-                        }
-                        self.results.sort_by_key(|n| *n);
-                        drop_n = *x;
-                        
-                    }
-                    DropDie::DropHighest(x) => {
-                        if *x >= self.num_rolls {
-                            return Ok(0);
-                        }
-                        self.results.sort_by_key(|n| *n);
-                        self.results.reverse();
-                        drop_n = *x;
-                    }
-                }
-                // And end with removing the dropped dice
-                self.results.drain(0..drop_n);
-            }
-            None => {}
+        let tracked_total = tracked.heap.iter().try_fold(0i64, |sum, x| sum.checked_add(x.abs())).ok_or(ArithmeticError::Overflow)?;
+        if track_kept {
+            Ok(tracked_total)
+        }
+        else {
+            Ok(total - tracked_total)
+        }
+    }
+
+    fn distribution(&self) -> Result<Distribution, Error> {
+        let (drop_n, drop_highest) = self.dropped();
+        Distribution::of_roll(self.num_rolls, self.dice_sides, self.num_rolls - drop_n, !drop_highest)
+    }
+}
+
+// Remembers the n smallest values pushed into it, and nothing else
+struct SmallestN {
+    n: usize,
+    // A max-heap, so the largest remembered value is the first to be evicted
+    heap: BinaryHeap<i64>
+}
+
+impl SmallestN {
+    fn new(n: usize) -> Self {
+        SmallestN { n, heap: BinaryHeap::with_capacity(n) }
+    }
+
+    fn push(&mut self, value: i64) {
+        if self.heap.len() < self.n {
+            self.heap.push(value);
+        }
+        else if let Some(mut largest) = self.heap.peek_mut() && value < *largest {
+            *largest = value;
         }
-        Ok((self.results.iter().sum::<u32>() as i32) as i64)
     }
 }
 
 impl Rollable for RollOrConstant {
-    fn roll(&mut self, rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>> {
+    fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error> {
         match self {
             Self::Roll(x) => x.roll(rng),
             Self::Const(x) => x.roll(rng)
         }
     }
 
-    fn roll_with_output(&mut self, output: &mut String, rng: &mut ThreadRng, skip_dropped: bool, colour: bool) -> Result<i64, Box<dyn std::error::Error>> {
+    fn roll_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error> {
+        match self {
+            Self::Roll(x) => x.roll_with_breakdown(rng),
+            Self::Const(x) => x.roll_with_breakdown(rng)
+        }
+    }
+
+    fn distribution(&self) -> Result<Distribution, Error> {
         match self {
-            Self::Roll(x) => x.roll_with_output(output, rng, skip_dropped, colour),
-            Self::Const(x) => x.roll_with_output(output, rng, skip_dropped, colour)
+            Self::Roll(x) => x.distribution(),
+            Self::Const(x) => x.distribution()
         }
     }
 }
 
-// The states of our FSM, used to consume the user input
+// The states of our FSM, used to consume the user input. Each is named for what is being
+// obtained from the input
 pub enum States {
-    ObtainingNumberOfDice,
-    ObtainingDiceSides,
-    ObtainingDropDieType,
-    ObtainingDropDieNum,
+    NumberOfDice,
+    DiceSides,
+    DropDieType,
+    KeepDieType,
+    DropDieNum,
+    CritType,
+    CritComparison,
+    CritNum,
 }
 
-pub fn consume_input_to_roll(input: &Vec<ASTInput>, index: &mut usize, help_message: &String) -> Result<RollOrConstant, Box<dyn std::error::Error>> {
+pub fn consume_input_to_roll(input: &[ASTInput], index: &mut usize, help_message: &String) -> Result<RollOrConstant, Error> {
     let mut new_roll = Roll::new();
     let mut new_const = ConstantRoll::new();
-    let mut state: States = States::ObtainingNumberOfDice;
-    // While loop so we can go back and forth between characters in our FSM
+    let mut state: States = States::NumberOfDice;
+    // The number read so far, which is a constant unless a d follows it
+    let mut number: i64 = 0;
+    // So that 0d6 is not mistaken for d6
+    let mut num_rolls_given = false;
+    // Where the d was, to point at a roll with no sides
+    let mut sides_position = 0;
+    // The crit or fumble range currently being read, i.e. cs>=19
+    let mut crit_is_fumble = false;
+    let mut crit_comparison = String::new();
+    let mut crit_value: u32 = 0;
+    let mut crit_position = 0;
+    // While loop so we can go back and forth between characters in our FSM.
+    // index is only moved past characters which belong to the roll, so that whatever
+    // ends the roll (i.e. an operator) is left for the caller
     while *index < input.len() {
-        let ASTInput::Character(character) = input[*index] else {break};
-        if character.is_whitespace() {continue}
-        *index += 1;
+        let ASTInput::Character(character, position) = input[*index] else {break};
+        if character.is_whitespace() {
+            *index += 1;
+            continue;
+        }
         // Depending on our current state,
         match state {
-            States::ObtainingDiceSides => {
-                if character.is_digit(10) {
-                    new_roll.dice_sides *= 10;
-                    new_roll.dice_sides += character.to_digit(10).ok_or("Expected character to be a digit")? as u8;
+            States::DiceSides => {
+                if let Some(digit) = character.to_digit(10) {
+                    new_roll.dice_sides = new_roll.dice_sides.checked_mul(10)
+                        .and_then(|x| x.checked_add(digit as u8))
+                        .ok_or(ParseError::new("Dice may have at most 255 sides", Some(position)))?;
+                }
+                else if new_roll.dice_sides == 0 {
+                    return Err(ParseError::new("Expected a number of sides after d", Some(sides_position)).into());
                 }
                 else {
                     match character {
@@ -263,87 +401,177 @@ pub fn consume_input_to_roll(input: &Vec<ASTInput>, index: &mut usize, help_mess
                             return Ok(RollOrConstant::Roll(new_roll));
                         }
                         'd' => {
-                            state = States::ObtainingDropDieType;
+                            state = States::DropDieType;
+                        }
+                        'k' => {
+                            state = States::KeepDieType;
+                        }
+                        'c' => {
+                            state = States::CritType;
                         }
                         _ => {
-                            println!("{}", character);
-                            return Err("Unknown character encountered".into());
+                            return Err(ParseError::new(format!("Unknown character encountered: {}", character), Some(position)).into());
                         }
                     }
                 }
             }
 
-            States::ObtainingNumberOfDice => {
-                if character.is_digit(10) {
-                    new_roll.num_rolls *= 10;
-                    new_roll.num_rolls += character.to_digit(10).ok_or("Expected character to be a digit")? as usize;
+            States::NumberOfDice => {
+                if let Some(digit) = character.to_digit(10) {
+                    number = number.checked_mul(10)
+                        .and_then(|x| x.checked_add(digit.into()))
+                        .ok_or(ArithmeticError::Overflow)?;
+                    num_rolls_given = true;
                 }
                 else {
                     match character {
                         'd' => {
-                            state = States::ObtainingDiceSides;
-                            if new_roll.num_rolls == 0 {
+                            state = States::DiceSides;
+                            sides_position = position;
+                            if num_rolls_given {
+                                new_roll.num_rolls = usize::try_from(number).map_err(|_| ArithmeticError::Overflow)?;
+                            }
+                            else {
                                 new_roll.num_rolls = 1;
                             }
                         }
-                        ' ' => {
-                            continue;
-                        }
                         // If we find a character we are not expecting, assume that this is a
                         // constant and parse the current character as part of the next operation
                         _ => {
-                            new_const.constant_result = new_roll.num_rolls as i64;
+                            new_const.constant_result = number;
                             return Ok(RollOrConstant::Const(new_const));
                         }
                     }
                 }
             }
 
-            States::ObtainingDropDieType => {
+            States::DropDieType => {
                 match character {
                     'l' => {
                         new_roll.drop_die = Some(DropDie::DropLowest(0));
-                        state = States::ObtainingDropDieNum;
+                        state = States::DropDieNum;
                     }
                     'h' => {
                         new_roll.drop_die = Some(DropDie::DropHighest(0));
-                        state = States::ObtainingDropDieNum;
+                        state = States::DropDieNum;
                     }
                     _ => {
-                        println!("{}", help_message);
-                        return Err("Unknown character encountered".into());
+                        // Library callers have no help message to show
+                        if !help_message.is_empty() {
+                            println!("{}", help_message);
+                        }
+                        return Err(ParseError::new(format!("Unknown character encountered: {}", character), Some(position)).into());
                     }
                 }
             }
-            States::ObtainingDropDieNum => {
-                if character.is_digit(10) {
-                    let drop_die = &new_roll.drop_die;
-                    match drop_die {
-                        Some(y) => {
-                            match y {
-                                DropDie::DropLowest(x) => {
-                                    new_roll.drop_die = Some(DropDie::DropLowest(x * 10 + character.to_digit(10).unwrap() as usize));
-                                }
-                                DropDie::DropHighest(x) => {
-                                    new_roll.drop_die = Some(DropDie::DropHighest(x * 10 + character.to_digit(10).unwrap() as usize));
-                                }
-                            }
+            States::KeepDieType => {
+                match character {
+                    'h' => new_roll.drop_die = Some(DropDie::KeepHighest(0)),
+                    'l' => new_roll.drop_die = Some(DropDie::KeepLowest(0)),
+                    _ => {
+                        return Err(ParseError::new("Expected kh or kl", Some(position)).into());
+                    }
+                }
+                state = States::DropDieNum;
+            }
+            States::DropDieNum => {
+                if let Some(digit) = character.to_digit(10) {
+                    let push_digit = |x: usize| x.checked_mul(10).and_then(|x| x.checked_add(digit as usize)).ok_or(ArithmeticError::Overflow);
+                    match &new_roll.drop_die {
+                        Some(DropDie::DropLowest(x)) => {
+                            new_roll.drop_die = Some(DropDie::DropLowest(push_digit(*x)?));
+                        }
+                        Some(DropDie::DropHighest(x)) => {
+                            new_roll.drop_die = Some(DropDie::DropHighest(push_digit(*x)?));
+                        }
+                        Some(DropDie::KeepHighest(x)) => {
+                            new_roll.drop_die = Some(DropDie::KeepHighest(push_digit(*x)?));
+                        }
+                        Some(DropDie::KeepLowest(x)) => {
+                            new_roll.drop_die = Some(DropDie::KeepLowest(push_digit(*x)?));
                         }
                         None => {
-                            return Err("Somehow got to ObtainingDropDieNum state without first getting to ObtainingDropDieType".into());
+                            return Err(ParseError::new("Somehow got to the DropDieNum state without first getting to DropDieType", Some(position)).into());
                         }
                     }
                 }
+                else if character == 'c' {
+                    state = States::CritType;
+                }
                 else {
                     return Ok(RollOrConstant::Roll(new_roll));
                 }
             }
+            States::CritType => {
+                match character {
+                    's' => crit_is_fumble = false,
+                    'f' => crit_is_fumble = true,
+                    _ => {
+                        return Err(ParseError::new("Expected cs or cf", Some(position)).into());
+                    }
+                }
+                crit_comparison.clear();
+                crit_value = 0;
+                crit_position = position;
+                state = States::CritComparison;
+            }
+            States::CritComparison | States::CritNum => {
+                if let Some(digit) = character.to_digit(10) {
+                    crit_value = crit_value.checked_mul(10).and_then(|x| x.checked_add(digit)).ok_or(ArithmeticError::Overflow)?;
+                    state = States::CritNum;
+                }
+                else if matches!(state, States::CritComparison) && matches!(character, '<' | '>' | '=') {
+                    crit_comparison.push(character);
+                }
+                else if matches!(state, States::CritComparison) {
+                    return Err(ParseError::new("Expected a number after the crit range", Some(position)).into());
+                }
+                else {
+                    set_crit_range(&mut new_roll, crit_is_fumble, &crit_comparison, crit_value, crit_position)?;
+                    if character == 'c' {
+                        state = States::CritType;
+                    }
+                    else if character == 'd' && new_roll.drop_die.is_none() {
+                        state = States::DropDieType;
+                    }
+                    else if character == 'k' && new_roll.drop_die.is_none() {
+                        state = States::KeepDieType;
+                    }
+                    else {
+                        return Ok(RollOrConstant::Roll(new_roll));
+                    }
+                }
+            }
         }
+        *index += 1;
+    }
+    // Running out of input before a d means that this was just a number
+    if matches!(state, States::NumberOfDice) {
+        Ok(RollOrConstant::Const(ConstantRoll { constant_result: number }))
+    }
+    else if matches!(state, States::DiceSides) && new_roll.dice_sides == 0 {
+        Err(ParseError::new("Expected a number of sides after d", Some(sides_position)).into())
     }
-    if new_roll.dice_sides == 0 {
-        Ok(RollOrConstant::Const(ConstantRoll { constant_result: new_roll.num_rolls as i64 }))
+    else if matches!(state, States::CritType | States::CritComparison) {
+        Err(ParseError::new("Incomplete crit range", None).into())
     }
     else {
+        if matches!(state, States::CritNum) {
+            set_crit_range(&mut new_roll, crit_is_fumble, &crit_comparison, crit_value, crit_position)?;
+        }
         Ok(RollOrConstant::Roll(new_roll))
     }
 }
+
+// Stores a finished cs/cf range on the roll
+fn set_crit_range(roll: &mut Roll, is_fumble: bool, comparison: &str, value: u32, position: usize) -> Result<(), ParseError> {
+    let range = FaceComparison::from_notation(comparison, value)
+        .ok_or(ParseError::new(format!("Unknown comparison: {}", comparison), Some(position)))?;
+    if is_fumble {
+        roll.fumble = Some(range);
+    }
+    else {
+        roll.crit = Some(range);
+    }
+    Ok(())
+}
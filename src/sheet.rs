@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "files")]
+use std::path::Path;
+use crate::error::ParseError;
+#[cfg(feature = "files")]
+use crate::error::Error;
+use crate::damage::DamageModifierKind;
+use crate::tree::CritRule;
+
+/// A character sheet: named values such as `str_mod = 3` or `athletics = "proficiency +
+/// str_mod"`, which may be used by name in expressions.
+///
+/// Sheets are read from TOML or JSON, with the `files` feature. Tables only group the values,
+/// so the names inside them are used on their own, and must not be repeated.
+///
+/// ```
+/// # #[cfg(feature = "files")] {
+/// use roll::{Expression, Sheet};
+///
+/// let sheet = Sheet::from_toml("
+/// proficiency = 2
+/// [abilities]
+/// str_mod = 3
+/// [skills]
+/// athletics = 'proficiency + str_mod'
+/// ").unwrap();
+/// let expression = Expression::parse(&sheet.expand("d20 + athletics").unwrap()).unwrap();
+/// assert_eq!(expression.to_string(), "d20 + (2 + 3)");
+/// assert!(sheet.expand("d20 + acrobatics").is_err());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sheet {
+    values: BTreeMap<String, String>
+}
+
+// Words which are part of the notation, rather than names on a sheet
+const KEYWORDS: &[&str] = &["adv", "dis", "vs"];
+
+// Whether a word is notation such as d20, d20a or dl1 rather than a name
+fn is_notation(word: &str) -> bool {
+    let mut characters = word.chars();
+    let dice = characters.next() == Some('d') && characters.next().is_some_and(|x| x.is_ascii_digit() || x == 'l' || x == 'h');
+    dice || KEYWORDS.contains(&word)
+}
+
+// Whether a word names a function, such as crit or resist, which a name would hide
+fn is_function(word: &str) -> bool {
+    let word = word.to_ascii_lowercase();
+    word == "fitd" || CritRule::from_name(&word).is_some() || DamageModifierKind::from_name(&word).is_some()
+}
+
+impl Sheet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "files")]
+    /// Reads a sheet from a file, as JSON if it ends in `.json` and as TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|x| Error::File(path.to_owned(), x))?;
+        if path.extension().is_some_and(|x| x.eq_ignore_ascii_case("json")) {
+            Self::from_json(&contents)
+        }
+        else {
+            Self::from_toml(&contents)
+        }
+    }
+
+    #[cfg(feature = "files")]
+    pub fn from_toml(input: &str) -> Result<Self, Error> {
+        let table: toml::Table = input.parse().map_err(|x: toml::de::Error| ParseError::new(format!("Invalid TOML sheet: {}", x.message()), None))?;
+        let mut sheet = Self::new();
+        sheet.add_toml(&table)?;
+        Ok(sheet)
+    }
+
+    #[cfg(feature = "files")]
+    pub fn from_json(input: &str) -> Result<Self, Error> {
+        let value: serde_json::Value = serde_json::from_str(input).map_err(|x| ParseError::new(format!("Invalid JSON sheet: {}", x), None))?;
+        let serde_json::Value::Object(object) = value else {
+            return Err(ParseError::new("A JSON sheet must be an object", None).into());
+        };
+        let mut sheet = Self::new();
+        sheet.add_json(&object)?;
+        Ok(sheet)
+    }
+
+    #[cfg(feature = "files")]
+    fn add_toml(&mut self, table: &toml::Table) -> Result<(), ParseError> {
+        for (name, value) in table {
+            match value {
+                toml::Value::Table(x) => self.add_toml(x)?,
+                toml::Value::Integer(x) => self.set(name, x.to_string())?,
+                toml::Value::String(x) => self.set(name, x.clone())?,
+                _ => return Err(ParseError::new(format!("{} must be a whole number or an expression", name), None))
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "files")]
+    fn add_json(&mut self, object: &serde_json::Map<String, serde_json::Value>) -> Result<(), ParseError> {
+        for (name, value) in object {
+            match value {
+                serde_json::Value::Object(x) => self.add_json(x)?,
+                serde_json::Value::Number(x) if x.is_i64() => self.set(name, x.to_string())?,
+                serde_json::Value::String(x) => self.set(name, x.clone())?,
+                _ => return Err(ParseError::new(format!("{} must be a whole number or an expression", name), None))
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a value, which may be a number or an expression using other names.
+    ///
+    /// Names must not look like notation, such as `d20` or `adv`, or be the name of a
+    /// function, such as `crit` or `resist`.
+    ///
+    /// ```
+    /// use roll::Sheet;
+    ///
+    /// let mut sheet = Sheet::new();
+    /// assert!(sheet.set("str_mod", "3".to_owned()).is_ok());
+    /// for name in ["d20", "adv", "crit", "critmax", "crittotal", "fitd", "resist", "Vulnerability", "immune"] {
+    ///     assert!(sheet.set(name, "2".to_owned()).is_err(), "{}", name);
+    /// }
+    /// assert_eq!(sheet.expand("crit(2d6) + str_mod").unwrap(), "crit(2d6) + 3");
+    /// ```
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), ParseError> {
+        let valid = name.chars().next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+            && name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_');
+        if !valid || is_notation(name) || is_function(name) {
+            return Err(ParseError::new(format!("{} cannot be used as a name", name), None));
+        }
+        if self.values.contains_key(name) {
+            return Err(ParseError::new(format!("{} is on the sheet more than once", name), None));
+        }
+        self.values.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    /// The value given for a name, as written on the sheet.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Replaces every name in an expression with its value, in brackets unless it is a plain
+    /// number. Labels and any comment are left alone, and a name which is not on the sheet is
+    /// an error.
+    pub fn expand(&self, input: &str) -> Result<String, ParseError> {
+        self.expand_within(input, &mut Vec::new())
+    }
+
+    // resolving holds the names being expanded, to catch values which refer to themselves
+    fn expand_within(&self, input: &str, resolving: &mut Vec<String>) -> Result<String, ParseError> {
+        let mut output = String::with_capacity(input.len());
+        let mut in_label = false;
+        let mut characters = input.char_indices().peekable();
+        while let Some((index, character)) = characters.next() {
+            match character {
+                '[' => in_label = true,
+                ']' => in_label = false,
+                '#' if !in_label => {
+                    output += &input[index..];
+                    break;
+                }
+                _ => {}
+            }
+            let starts_word = (character.is_ascii_alphabetic() || character == '_')
+                && !input[..index].chars().next_back().is_some_and(|x| x.is_ascii_alphanumeric() || x == '_');
+            if in_label || !starts_word {
+                output.push(character);
+                continue;
+            }
+            let mut end = index + character.len_utf8();
+            while let Some((next_index, next)) = characters.peek().copied() && (next.is_ascii_alphanumeric() || next == '_') {
+                end = next_index + next.len_utf8();
+                characters.next();
+            }
+            let word = &input[index..end];
+            // Functions and modifiers, such as crit( and resist(, are notation too
+            let is_function = input[end..].trim_start().starts_with('(');
+            if is_function && DamageModifierKind::from_name(&word.to_ascii_lowercase()).is_some() {
+                // The damage type in resist(fire) is not a name either
+                let close = input[end..].find(')').map_or(input.len(), |x| end + x + 1);
+                output += &input[index..close];
+                while characters.peek().is_some_and(|(x, _)| *x < close) {
+                    characters.next();
+                }
+                continue;
+            }
+            match self.values.get(word) {
+                _ if is_notation(word) || (is_function && !self.values.contains_key(word)) => output += word,
+                Some(value) => {
+                    if resolving.iter().any(|x| x == word) {
+                        return Err(ParseError::new(format!("{} refers to itself", word), None));
+                    }
+                    resolving.push(word.to_owned());
+                    let expanded = self.expand_within(value, resolving)?;
+                    resolving.pop();
+                    // Keeping the value together, unless it is a plain number. There is no
+                    // unary minus, so a negative one is taken from 0
+                    match expanded.trim().parse::<i64>() {
+                        Ok(x) if x < 0 => output += &format!("(0 - {})", x.unsigned_abs()),
+                        Ok(x) => output += &x.to_string(),
+                        Err(_) => output += &format!("({})", expanded.trim())
+                    }
+                }
+                // Positions inside a value would not match the expression as it was written
+                None if resolving.is_empty() => return Err(ParseError::new(format!("Unknown name: {}", word), Some(input[..index].chars().count()))),
+                None => return Err(ParseError::new(format!("Unknown name: {} (in {})", word, resolving.last().unwrap()), None))
+            }
+        }
+        Ok(output)
+    }
+}
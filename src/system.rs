@@ -0,0 +1,410 @@
+use std::fmt;
+use std::sync::Arc;
+use rand::Rng;
+use crate::breakdown::{Breakdown, BreakdownKind, DieResult};
+use crate::expression::Expression;
+use crate::tree::CritRule;
+use crate::error::Error;
+
+/// A game's rules for writing and reading rolls, such as 5e or PbtA.
+///
+/// A system may add its own shorthands to the notation, and may explain what a result means
+/// under its rules (i.e. "partial" for a 7 in PbtA).
+///
+/// ```
+/// use roll::{system_by_name, System};
+///
+/// let pbta = system_by_name("pbta").unwrap();
+/// let expression = pbta.parse("move + 1").unwrap();
+/// assert_eq!(expression.to_string(), "2d6 + 1");
+/// let breakdown = expression.roll_with_breakdown(&mut rand::rng()).unwrap();
+/// let reading = pbta.interpret(&breakdown).unwrap();
+/// assert!(["miss", "partial", "hit"].contains(&reading.as_str()));
+/// ```
+pub trait System: fmt::Debug + Send + Sync {
+    /// The name the system is chosen by, i.e. `pbta`.
+    fn name(&self) -> &'static str;
+
+    /// Rewrites the system's shorthands into plain notation, before it is parsed.
+    fn expand_shorthands(&self, input: &str) -> String {
+        input.to_owned()
+    }
+
+    /// What a rolled expression means under this system, if anything.
+    fn interpret(&self, _breakdown: &Breakdown) -> Option<String> {
+        None
+    }
+
+    /// How well a roll against a DC (i.e. `d20 + 7 vs 15`) went, for systems with degrees of
+    /// success. Others simply hit or miss.
+    fn degree_of_success(&self, _breakdown: &Breakdown, _dc: i64) -> Option<DegreeOfSuccess> {
+        None
+    }
+
+    /// How a critical hit adds to damage.
+    fn crit_rule(&self) -> CritRule {
+        CritRule::DoubleDice
+    }
+
+    /// Parses an expression, allowing for the system's shorthands.
+    fn parse(&self, input: &str) -> Result<Expression, Error> {
+        Expression::parse(&self.expand_shorthands(input))
+    }
+}
+
+/// How well a check went, for systems which grade them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DegreeOfSuccess {
+    CriticalFailure,
+    Failure,
+    Success,
+    CriticalSuccess
+}
+
+impl DegreeOfSuccess {
+    pub fn is_success(&self) -> bool {
+        *self >= Self::Success
+    }
+
+    // One degree better, or worse, stopping at either end
+    fn step_up(self) -> Self {
+        match self {
+            Self::CriticalFailure => Self::Failure,
+            Self::Failure => Self::Success,
+            Self::Success | Self::CriticalSuccess => Self::CriticalSuccess
+        }
+    }
+
+    fn step_down(self) -> Self {
+        match self {
+            Self::CriticalSuccess => Self::Success,
+            Self::Success => Self::Failure,
+            Self::Failure | Self::CriticalFailure => Self::CriticalFailure
+        }
+    }
+}
+
+impl fmt::Display for DegreeOfSuccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CriticalFailure => write!(f, "critical failure"),
+            Self::Failure => write!(f, "failure"),
+            Self::Success => write!(f, "success"),
+            Self::CriticalSuccess => write!(f, "critical success")
+        }
+    }
+}
+
+/// A check rolled against a DC, with how well it went.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Check {
+    pub roll: Breakdown,
+    pub dc: i64,
+    pub degree: DegreeOfSuccess
+}
+
+/// How a Forged in the Dark roll went, read from its highest die.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FitdOutcome {
+    /// More than one 6
+    Critical,
+    /// A 6
+    FullSuccess,
+    /// A 4 or 5
+    PartialSuccess,
+    /// A 1, 2 or 3
+    BadOutcome
+}
+
+impl fmt::Display for FitdOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Critical => write!(f, "critical"),
+            Self::FullSuccess => write!(f, "full success"),
+            Self::PartialSuccess => write!(f, "partial success"),
+            Self::BadOutcome => write!(f, "bad outcome")
+        }
+    }
+}
+
+/// The names of every built-in system, as accepted by [`system_by_name`].
+pub const SYSTEM_NAMES: &[&str] = &["5e", "pf2e", "pbta", "fitd", "yz"];
+
+/// Looks up a built-in system by its name, or by a common alias such as `blades`.
+pub fn system_by_name(name: &str) -> Option<Arc<dyn System>> {
+    match name.to_ascii_lowercase().as_str() {
+        "5e" | "dnd5e" | "dnd" => Some(Arc::new(Dnd5e)),
+        "pf2e" | "pathfinder" => Some(Arc::new(Pf2e)),
+        "pbta" => Some(Arc::new(Pbta)),
+        "fitd" | "blades" => Some(Arc::new(ForgedInTheDark)),
+        "yz" | "yearzero" => Some(Arc::new(YearZero)),
+        _ => None
+    }
+}
+
+// Replaces every whole-word occurrence of word, i.e. stat in stat + 1 but not in status
+fn replace_word(input: &str, word: &str, replacement: &str) -> String {
+    let is_word_character = |x: Option<char>| x.is_some_and(|x| x.is_ascii_alphanumeric());
+    let mut output = String::with_capacity(input.len());
+    let mut copied_up_to = 0;
+    for (index, _) in input.match_indices(word) {
+        let before = input[..index].chars().next_back();
+        let after = input[index + word.len()..].chars().next();
+        if !is_word_character(before) && !is_word_character(after) {
+            output += &input[copied_up_to..index];
+            output += replacement;
+            copied_up_to = index + word.len();
+        }
+    }
+    output + &input[copied_up_to..]
+}
+
+// The dice which counted towards a total with the given number of sides
+fn kept_dice(breakdown: &Breakdown, sides: u8) -> Vec<&DieResult> {
+    breakdown.dice().into_iter().filter(|x| x.sides == sides && !x.dropped).collect()
+}
+
+// Points out a lone d20 showing a 20 or a 1
+fn natural_d20(breakdown: &Breakdown) -> Option<String> {
+    match kept_dice(breakdown, 20).as_slice() {
+        [x] if x.face == 20 => Some("natural 20".to_owned()),
+        [x] if x.face == 1 => Some("natural 1".to_owned()),
+        _ => None
+    }
+}
+
+/// Dungeons & Dragons 5th edition. `stat` rolls an ability score (`4d6kh3`), and natural
+/// 20s and 1s are pointed out. Advantage and disadvantage are always available.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dnd5e;
+
+impl System for Dnd5e {
+    fn name(&self) -> &'static str {
+        "5e"
+    }
+
+    fn expand_shorthands(&self, input: &str) -> String {
+        replace_word(input, "stat", "4d6kh3")
+    }
+
+    fn interpret(&self, breakdown: &Breakdown) -> Option<String> {
+        natural_d20(breakdown)
+    }
+}
+
+/// Pathfinder 2nd edition. `flat` rolls a flat check (`d20`), and natural 20s and 1s are
+/// pointed out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pf2e;
+
+impl System for Pf2e {
+    fn name(&self) -> &'static str {
+        "pf2e"
+    }
+
+    fn expand_shorthands(&self, input: &str) -> String {
+        replace_word(input, "flat", "d20")
+    }
+
+    fn interpret(&self, breakdown: &Breakdown) -> Option<String> {
+        natural_d20(breakdown)
+    }
+
+    fn degree_of_success(&self, breakdown: &Breakdown, dc: i64) -> Option<DegreeOfSuccess> {
+        Some(Self::degree(breakdown, dc))
+    }
+
+    // A critical hit doubles all of the damage
+    fn crit_rule(&self) -> CritRule {
+        CritRule::DoubleTotal
+    }
+}
+
+impl Pf2e {
+    /// Rolls a check, such as `d20 + 7`, against a DC.
+    ///
+    /// ```
+    /// use roll::{Expression, Pf2e};
+    ///
+    /// let check = Pf2e.check(&Expression::parse("d20 + 7").unwrap(), 15, &mut rand::rng()).unwrap();
+    /// assert_eq!(check.dc, 15);
+    /// assert_eq!(check.degree, Pf2e::degree(&check.roll, 15));
+    /// ```
+    pub fn check<R: Rng + ?Sized>(&self, expression: &Expression, dc: i64, rng: &mut R) -> Result<Check, Error> {
+        let roll = expression.roll_with_breakdown(rng)?;
+        let degree = Self::degree(&roll, dc);
+        Ok(Check { roll, dc, degree })
+    }
+
+    /// How well a rolled check went against a DC. Beating the DC by 10 is a critical success
+    /// and missing it by 10 a critical failure, then a natural 20 or 1 on the d20 moves the
+    /// result one degree up or down.
+    ///
+    /// ```
+    /// use roll::{Breakdown, BreakdownKind, DieResult, Operator, Pf2e};
+    /// use roll::DegreeOfSuccess::*;
+    ///
+    /// // The d20s of a check, each given as its face and whether it was dropped, plus 7
+    /// let check = |dice: &[(u32, bool)]| {
+    ///     let dice: Vec<DieResult> = dice.iter().map(|&(face, dropped)| DieResult { face, sides: 20, dropped, crit: false, fumble: false }).collect();
+    ///     let rolled = dice.iter().filter(|x| !x.dropped).map(|x| i64::from(x.face)).sum();
+    ///     let dice = Breakdown::new(rolled, BreakdownKind::Roll(dice));
+    ///     let modifier = Breakdown::new(7, BreakdownKind::Constant);
+    ///     Breakdown::new(rolled + 7, BreakdownKind::Operation(Operator::Add, Box::new(dice), Box::new(modifier)))
+    /// };
+    /// assert_eq!(Pf2e::degree(&check(&[(13, false)]), 20), Success);
+    /// assert_eq!(Pf2e::degree(&check(&[(12, false)]), 20), Failure);
+    /// assert_eq!(Pf2e::degree(&check(&[(13, false)]), 10), CriticalSuccess);
+    /// assert_eq!(Pf2e::degree(&check(&[(12, false)]), 10), Success);
+    /// assert_eq!(Pf2e::degree(&check(&[(3, false)]), 20), CriticalFailure);
+    /// assert_eq!(Pf2e::degree(&check(&[(4, false)]), 20), Failure);
+    /// // A natural 20 or 1 moves the result a degree, even with a dropped d20 beside it
+    /// assert_eq!(Pf2e::degree(&check(&[(20, false)]), 35), Success);
+    /// assert_eq!(Pf2e::degree(&check(&[(1, false)]), 5), Failure);
+    /// assert_eq!(Pf2e::degree(&check(&[(3, true), (20, false)]), 35), Success);
+    /// // But not when more than one d20 is kept
+    /// assert_eq!(Pf2e::degree(&check(&[(20, false), (5, false)]), 40), Failure);
+    /// assert_eq!(Pf2e::degree(&check(&[(1, false), (5, false)]), 10), Success);
+    /// ```
+    pub fn degree(breakdown: &Breakdown, dc: i64) -> DegreeOfSuccess {
+        let total = i128::from(breakdown.total);
+        let dc = i128::from(dc);
+        let mut degree = if total >= dc + 10 {
+            DegreeOfSuccess::CriticalSuccess
+        }
+        else if total >= dc {
+            DegreeOfSuccess::Success
+        }
+        else if total <= dc - 10 {
+            DegreeOfSuccess::CriticalFailure
+        }
+        else {
+            DegreeOfSuccess::Failure
+        };
+        match kept_dice(breakdown, 20).as_slice() {
+            [x] if x.face == 20 => degree = degree.step_up(),
+            [x] if x.face == 1 => degree = degree.step_down(),
+            _ => {}
+        }
+        degree
+    }
+}
+
+/// Powered by the Apocalypse. `move` rolls `2d6`, and totals are read as 6-: miss,
+/// 7-9: partial, 10+: hit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pbta;
+
+impl System for Pbta {
+    fn name(&self) -> &'static str {
+        "pbta"
+    }
+
+    fn expand_shorthands(&self, input: &str) -> String {
+        replace_word(input, "move", "2d6")
+    }
+
+    fn interpret(&self, breakdown: &Breakdown) -> Option<String> {
+        let band = match breakdown.total {
+            ..=6 => "miss",
+            7..=9 => "partial",
+            10.. => "hit"
+        };
+        Some(band.to_owned())
+    }
+}
+
+/// Forged in the Dark (i.e. Blades in the Dark). A pool of d6s, written `fitd(3)`, is read
+/// by its highest die: two or more 6s are a critical, a 6 a full success, a 4 or 5 a partial
+/// success and anything else a bad outcome.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ForgedInTheDark;
+
+impl System for ForgedInTheDark {
+    fn name(&self) -> &'static str {
+        "fitd"
+    }
+
+    fn interpret(&self, breakdown: &Breakdown) -> Option<String> {
+        Self::outcome(breakdown).map(|x| x.to_string())
+    }
+}
+
+impl ForgedInTheDark {
+    /// Reads a rolled pool, such as `fitd(3)` (`3d6kh1`). Every 6 in the pool counts towards
+    /// a critical, even those not kept, apart from with zero dice (`fitd(0)`, which rolls 2d6
+    /// keeping the lowest) where only the lower die counts and there can be no critical.
+    ///
+    /// ```
+    /// use roll::{Breakdown, BreakdownKind, DieResult, Expression, FitdOutcome, ForgedInTheDark};
+    ///
+    /// let breakdown = Expression::parse("fitd(0)").unwrap().roll_with_breakdown(&mut rand::rng()).unwrap();
+    /// assert!(matches!(breakdown.kind, BreakdownKind::ZeroDice(_)));
+    /// assert_eq!(breakdown.dice().len(), 2);
+    ///
+    /// let die = |face, dropped| DieResult { face, sides: 6, dropped, crit: false, fumble: false };
+    /// // Two 6s are a critical, even when one of them is dropped
+    /// let pool = Breakdown::new(6, BreakdownKind::Roll(vec![die(2, true), die(6, true), die(6, false)]));
+    /// assert_eq!(ForgedInTheDark::outcome(&pool), Some(FitdOutcome::Critical));
+    /// let pool = Breakdown::new(6, BreakdownKind::Roll(vec![die(2, true), die(5, true), die(6, false)]));
+    /// assert_eq!(ForgedInTheDark::outcome(&pool), Some(FitdOutcome::FullSuccess));
+    ///
+    /// // But on zero dice they are only a full success, even with a label of its own
+    /// assert_eq!(Expression::parse("fitd(0)[stealth]").unwrap().to_string(), "fitd(0)[stealth]");
+    /// let mut zero_dice = Breakdown::new(6, BreakdownKind::ZeroDice(vec![die(6, true), die(6, false)]));
+    /// zero_dice.label = Some("stealth".to_owned());
+    /// assert_eq!(ForgedInTheDark::outcome(&zero_dice), Some(FitdOutcome::FullSuccess));
+    /// let zero_dice = Breakdown::new(3, BreakdownKind::ZeroDice(vec![die(6, true), die(3, false)]));
+    /// assert_eq!(ForgedInTheDark::outcome(&zero_dice), Some(FitdOutcome::BadOutcome));
+    /// ```
+    pub fn outcome(breakdown: &Breakdown) -> Option<FitdOutcome> {
+        let highest = kept_dice(breakdown, 6).iter().map(|x| x.face).max()?;
+        let zero_dice = has_zero_dice(breakdown);
+        let sixes = breakdown.dice().into_iter().filter(|x| x.sides == 6 && x.face == 6).count();
+        let outcome = match highest {
+            6 if sixes > 1 && !zero_dice => FitdOutcome::Critical,
+            6 => FitdOutcome::FullSuccess,
+            4 | 5 => FitdOutcome::PartialSuccess,
+            _ => FitdOutcome::BadOutcome
+        };
+        Some(outcome)
+    }
+}
+
+// Whether any part of a breakdown is a pool of zero dice
+fn has_zero_dice(breakdown: &Breakdown) -> bool {
+    match &breakdown.kind {
+        BreakdownKind::Operation(_, x, y) => has_zero_dice(x) || has_zero_dice(y),
+        BreakdownKind::Damage(x, _) => has_zero_dice(x),
+        BreakdownKind::ZeroDice(_) => true,
+        BreakdownKind::Roll(_) | BreakdownKind::Constant => false
+    }
+}
+
+/// Year Zero Engine (i.e. Mutant: Year Zero, Alien). Every 6 rolled on a d6 is a success.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YearZero;
+
+impl System for YearZero {
+    fn name(&self) -> &'static str {
+        "yz"
+    }
+
+    fn interpret(&self, breakdown: &Breakdown) -> Option<String> {
+        let dice = kept_dice(breakdown, 6);
+        if dice.is_empty() {
+            return None;
+        }
+        let successes = dice.iter().filter(|x| x.face == 6).count();
+        match successes {
+            0 => Some("no successes".to_owned()),
+            1 => Some("1 success".to_owned()),
+            x => Some(format!("{} successes", x))
+        }
+    }
+}
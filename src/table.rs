@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use rand::Rng;
+use crate::error::{Error, LimitError, ParseError};
+use crate::expression::Expression;
+use crate::limits::Limits;
+
+// How many times a table may be rerolled before giving up, as a table of nothing but
+// rerolls would never finish
+const MAX_REROLLS: usize = 100;
+
+/// The file formats a [`Table`] may be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Toml,
+    Yaml
+}
+
+/// One row of a table, such as `01-15: Goblins`, covering the totals from low to high.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableEntry {
+    pub low: i64,
+    pub high: i64,
+    pub text: String
+}
+
+/// A random table, rolled on with a die expression such as `d100`.
+///
+/// An entry's text may roll dice of its own, as in `{2d6} goblins`, and roll on another
+/// table, as in `a chest holding [[treasure.csv]]`, whose path is relative to this table's.
+/// An entry of just `reroll` rolls on the table again.
+///
+/// ```
+/// use roll::{Table, TableFormat};
+///
+/// let table = Table::parse("d6,Encounter\n1-3,Nothing\n4-5,{1d4} goblins\n6,reroll", TableFormat::Csv).unwrap();
+/// assert_eq!(table.die().to_string(), "d6");
+/// let result = table.roll(&mut rand::rng()).unwrap();
+/// assert!(result.text == "Nothing" || result.text.ends_with(" goblins"));
+/// assert!(result.totals.last().is_some_and(|x| *x <= 5));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    die: Expression,
+    entries: Vec<TableEntry>,
+    // Where nested tables are found, if not the working directory
+    directory: Option<PathBuf>,
+    limits: Limits
+}
+
+// What one roll on a table has used so far, shared with every table nested inside it
+struct NestedRolls {
+    // Each nested table, loaded the first time it is rolled on
+    tables: HashMap<PathBuf, Rc<Table>>,
+    // Every die rolled, across all of the tables, which is bounded by the total dice limit
+    dice: usize
+}
+
+/// The result of rolling on a [`Table`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRoll {
+    /// Every total rolled, with any rerolls before the one which was used
+    pub totals: Vec<i64>,
+    /// The entry's text, with its dice and any nested tables rolled
+    pub text: String
+}
+
+impl TableFormat {
+    /// The format a file is in, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None
+        }
+    }
+}
+
+// A range such as 01-15 or 16, where 00 stands for 100 as on percentile dice
+fn parse_range(input: &str) -> Option<(i64, i64)> {
+    let number = |x: &str| match x.trim() {
+        "00" => Some(100),
+        x => x.parse::<i64>().ok()
+    };
+    let (low, high) = match input.trim().split_once(['-', '–']) {
+        Some((low, high)) => (number(low)?, number(high)?),
+        None => (number(input)?, number(input)?)
+    };
+    if low > high {
+        return None;
+    }
+    Some((low, high))
+}
+
+impl Table {
+    /// Reads a table from a file, choosing its format by its extension, and applying the
+    /// default [`Limits`].
+    ///
+    /// ```
+    /// use roll::{Error, LimitError, Limits, Table};
+    ///
+    /// // A table which rolls on itself is stopped by the nesting limit
+    /// let path = std::env::temp_dir().join("roll_doctest_itself.csv");
+    /// std::fs::write(&path, "d1,Itself\n1,[[roll_doctest_itself.csv]] and [[roll_doctest_itself.csv]]").unwrap();
+    /// let table = Table::load_with_limits(&path, &Limits::default().max_nesting_depth(8)).unwrap();
+    /// let error = table.roll(&mut rand::rng()).unwrap_err();
+    /// assert!(matches!(error, Error::Limit(LimitError::NestingDepth(8))));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::load_with_limits(path, &Limits::default())
+    }
+
+    pub fn load_with_limits(path: impl AsRef<Path>, limits: &Limits) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let Some(format) = TableFormat::from_path(path) else {
+            return Err(ParseError::new(format!("{} is not a .csv, .toml or .yaml table", path.display()), None).into());
+        };
+        let contents = std::fs::read_to_string(path).map_err(|x| Error::File(path.to_owned(), x))?;
+        let mut table = Self::parse_with_limits(&contents, format, limits)?;
+        table.directory = path.parent().map(Path::to_path_buf);
+        Ok(table)
+    }
+
+    /// Parses a table, applying the default [`Limits`].
+    ///
+    /// A CSV table has a range and its text on each row, and may start with a row giving
+    /// its die. TOML and YAML tables map each range to its text, either at the top level or
+    /// under `entries`, and may give their die as `die`. Without a die, one is chosen to
+    /// cover the highest range, i.e. `d20`.
+    pub fn parse(input: &str, format: TableFormat) -> Result<Self, Error> {
+        Self::parse_with_limits(input, format, &Limits::default())
+    }
+
+    pub fn parse_with_limits(input: &str, format: TableFormat, limits: &Limits) -> Result<Self, Error> {
+        let mut die: Option<String> = None;
+        let mut rows: Vec<(String, String)> = Vec::new();
+        match format {
+            TableFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).trim(csv::Trim::All)
+                    .from_reader(input.as_bytes());
+                for (row_index, record) in reader.records().enumerate() {
+                    let record = record.map_err(|x| ParseError::new(format!("Invalid CSV table: {}", x), None))?;
+                    let (Some(range), Some(text)) = (record.get(0), record.get(1)) else {continue};
+                    // The first row may give the die, or just be a heading
+                    if row_index == 0 && parse_range(range).is_none() {
+                        if Expression::parse_with_limits(range, limits).is_ok() {
+                            die = Some(range.to_owned());
+                        }
+                        continue;
+                    }
+                    rows.push((range.to_owned(), text.to_owned()));
+                }
+            }
+            TableFormat::Toml => {
+                let table: toml::Table = input.parse().map_err(|x: toml::de::Error| ParseError::new(format!("Invalid TOML table: {}", x.message()), None))?;
+                for (key, value) in &table {
+                    match (key.as_str(), value) {
+                        ("die", toml::Value::String(x)) => die = Some(x.clone()),
+                        ("entries", toml::Value::Table(x)) => {
+                            for (range, text) in x {
+                                rows.push((range.clone(), toml_text(text)?));
+                            }
+                        }
+                        (range, text) => rows.push((range.to_owned(), toml_text(text)?))
+                    }
+                }
+            }
+            TableFormat::Yaml => {
+                let value: serde_yaml_ng::Value = serde_yaml_ng::from_str(input).map_err(|x| ParseError::new(format!("Invalid YAML table: {}", x), None))?;
+                let serde_yaml_ng::Value::Mapping(mapping) = value else {
+                    return Err(ParseError::new("A YAML table must be a mapping of ranges to entries", None).into());
+                };
+                for (key, value) in &mapping {
+                    let key = yaml_text(key)?;
+                    match (key.as_str(), value) {
+                        ("die", x) => die = Some(yaml_text(x)?),
+                        ("entries", serde_yaml_ng::Value::Mapping(x)) => {
+                            for (range, text) in x {
+                                rows.push((yaml_text(range)?, yaml_text(text)?));
+                            }
+                        }
+                        (_, text) => rows.push((key.clone(), yaml_text(text)?))
+                    }
+                }
+            }
+        }
+        let mut entries = Vec::with_capacity(rows.len());
+        for (range, text) in rows {
+            let Some((low, high)) = parse_range(&range) else {
+                return Err(ParseError::new(format!("Expected a range such as 01-15, not {}", range), None).into());
+            };
+            if let Some(x) = entries.iter().find(|x: &&TableEntry| low <= x.high && x.low <= high) {
+                return Err(ParseError::new(format!("The range {} overlaps {}-{}", range, x.low, x.high), None).into());
+            }
+            entries.push(TableEntry { low, high, text });
+        }
+        if entries.is_empty() {
+            return Err(ParseError::new("The table has no entries", None).into());
+        }
+        entries.sort_by_key(|x| x.low);
+        let die = match die {
+            Some(x) => x,
+            None => format!("d{}", entries.iter().map(|x| x.high).max().unwrap_or(1))
+        };
+        let die = Expression::parse_with_limits(&die, limits)?;
+        Ok(Table { die, entries, directory: None, limits: *limits })
+    }
+
+    /// The expression rolled to pick an entry.
+    pub fn die(&self) -> &Expression {
+        &self.die
+    }
+
+    /// Every entry, from the lowest range to the highest.
+    pub fn entries(&self) -> &[TableEntry] {
+        &self.entries
+    }
+
+    /// Rolls on the table, following any rerolls and nested tables. Each nested table is
+    /// read once per roll, and the dice rolled across every table count towards the total
+    /// dice limit.
+    ///
+    /// ```
+    /// use roll::{Error, LimitError, Limits, Table, TableFormat};
+    ///
+    /// let table = Table::parse_with_limits("d6,Loot\n1-6,{3d6} coins", TableFormat::Csv, &Limits::default().max_total_dice(3)).unwrap();
+    /// let error = table.roll(&mut rand::rng()).unwrap_err();
+    /// assert!(matches!(error, Error::Limit(LimitError::TotalDice(3))));
+    /// ```
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<TableRoll, Error> {
+        self.roll_nested(rng, 0, &mut NestedRolls { tables: HashMap::new(), dice: 0 })
+    }
+
+    // depth counts the tables this one is nested inside
+    fn roll_nested<R: Rng + ?Sized>(&self, rng: &mut R, depth: usize, rolls: &mut NestedRolls) -> Result<TableRoll, Error> {
+        if depth > self.limits.max_nesting_depth {
+            return Err(LimitError::NestingDepth(self.limits.max_nesting_depth).into());
+        }
+        let mut totals = Vec::new();
+        while totals.len() <= MAX_REROLLS {
+            let total = self.roll_expression(&self.die, rng, rolls)?;
+            totals.push(total);
+            let Some(entry) = self.entries.iter().find(|x| x.low <= total && total <= x.high) else {
+                return Err(ParseError::new(format!("The table has no entry for {}", total), None).into());
+            };
+            if entry.text.trim().eq_ignore_ascii_case("reroll") {
+                continue;
+            }
+            let text = self.resolve(&entry.text, rng, depth, rolls)?;
+            return Ok(TableRoll { totals, text });
+        }
+        Err(ParseError::new(format!("Rerolled more than {} times", MAX_REROLLS), None).into())
+    }
+
+    // Rolls an expression, counting its dice towards the roll's total
+    fn roll_expression<R: Rng + ?Sized>(&self, expression: &Expression, rng: &mut R, rolls: &mut NestedRolls) -> Result<i64, Error> {
+        rolls.dice = rolls.dice.saturating_add(expression.ast().count_dice(&self.limits)?);
+        if rolls.dice > self.limits.max_total_dice {
+            return Err(LimitError::TotalDice(self.limits.max_total_dice).into());
+        }
+        expression.roll(rng)
+    }
+
+    // Rolls the dice in {2d6} and the tables in [[treasure.csv]] within an entry's text
+    fn resolve<R: Rng + ?Sized>(&self, text: &str, rng: &mut R, depth: usize, rolls: &mut NestedRolls) -> Result<String, Error> {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        loop {
+            let dice = rest.find('{');
+            let table = rest.find("[[");
+            let start = match (dice, table) {
+                (Some(x), Some(y)) => x.min(y),
+                (Some(x), None) | (None, Some(x)) => x,
+                (None, None) => break
+            };
+            output += &rest[..start];
+            if Some(start) == dice {
+                let Some(length) = rest[start..].find('}') else {
+                    return Err(ParseError::new(format!("Mismatched braces in {}", text), None).into());
+                };
+                let expression = Expression::parse_with_limits(&rest[start + 1..start + length], &self.limits)?;
+                output += &self.roll_expression(&expression, rng, rolls)?.to_string();
+                rest = &rest[start + length + 1..];
+            }
+            else {
+                let Some(length) = rest[start..].find("]]") else {
+                    return Err(ParseError::new(format!("Mismatched table brackets in {}", text), None).into());
+                };
+                let name = rest[start + 2..start + length].trim();
+                let path = match &self.directory {
+                    Some(x) => x.join(name),
+                    None => PathBuf::from(name)
+                };
+                let nested = match rolls.tables.get(&path) {
+                    Some(x) => Rc::clone(x),
+                    None => {
+                        let nested = Rc::new(Self::load_with_limits(&path, &self.limits)?);
+                        rolls.tables.insert(path, Rc::clone(&nested));
+                        nested
+                    }
+                };
+                output += &nested.roll_nested(rng, depth + 1, rolls)?.text;
+                rest = &rest[start + length + 2..];
+            }
+        }
+        output += rest;
+        Ok(output)
+    }
+}
+
+// The text of an entry in a TOML table, which may also be a number
+fn toml_text(value: &toml::Value) -> Result<String, ParseError> {
+    match value {
+        toml::Value::String(x) => Ok(x.clone()),
+        toml::Value::Integer(x) => Ok(x.to_string()),
+        _ => Err(ParseError::new("Table entries must be text", None))
+    }
+}
+
+// The text of a range or an entry in a YAML table, where either may be a number
+fn yaml_text(value: &serde_yaml_ng::Value) -> Result<String, ParseError> {
+    match value {
+        serde_yaml_ng::Value::String(x) => Ok(x.clone()),
+        serde_yaml_ng::Value::Number(x) => Ok(x.to_string()),
+        _ => Err(ParseError::new("Table ranges and entries must be text", None))
+    }
+}
+
+impl fmt::Display for TableRoll {
+    // i.e. 97 (reroll), 42 => Goblins
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, total) in self.totals.iter().enumerate() {
+            if index + 1 < self.totals.len() {
+                write!(f, "{} (reroll), ", total)?;
+            }
+            else {
+                write!(f, "{} => {}", total, self.text)?;
+            }
+        }
+        Ok(())
+    }
+}
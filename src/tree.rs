@@ -1,48 +1,217 @@
-use rand::{rngs::ThreadRng};
+use std::fmt;
+use rand::Rng;
 use crate::roll::*;
+use crate::error::{ArithmeticError, Error, LimitError, ParseError};
+use crate::limits::Limits;
+use crate::breakdown::{Breakdown, BreakdownKind, Operator};
+use crate::distribution::Distribution;
+use crate::damage::{apply_damage_modifiers, modifiers_within, DamageModifier};
 
+// Joins the operands either side of the operator at input_idx into one tree,
+// evaluating to whether there were operands to join
 macro_rules! handle_operator {
     ($operator:ident, $input:ident, $input_idx:ident) => {
-        if let ASTInput::PartialAST(_first) = &$input[$input_idx - 1] && let ASTInput::PartialAST(_second) = &$input[$input_idx + 1] {
+        if $input_idx > 0 && $input_idx + 1 < $input.len() && let ASTInput::PartialAST(_first) = &$input[$input_idx - 1] && let ASTInput::PartialAST(_second) = &$input[$input_idx + 1] {
             let ASTInput::PartialAST(second) = $input.remove($input_idx + 1) else {unreachable!()};
             let ASTInput::PartialAST(first) = $input.remove($input_idx - 1) else {unreachable!()};
             $input[$input_idx - 1] = ASTInput::PartialAST(AST::new(ASType::$operator(Box::new(first), Box::new(second)), false));
+            true
+        }
+        else {
+            false
         }
     };
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum ASTInput {
-    Character(char),
+    // A character alongside its offset into the original input, for error messages
+    Character(char, usize),
+    // The text of a label such as [fire], alongside the offset of its opening bracket
+    Label(String, usize),
     PartialAST(AST)
 }
 
+// Splits input on every separator which is not inside a [label]
+pub(crate) fn split_outside_labels(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut part_start = 0;
+    let mut in_label = false;
+    for (index, character) in input.char_indices() {
+        match character {
+            '[' => in_label = true,
+            ']' => in_label = false,
+            x if x == separator && !in_label => {
+                parts.push(&input[part_start..index]);
+                part_start = index + x.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[part_start..]);
+    parts
+}
+
+// Separates a trailing # comment from the expression before it
+pub(crate) fn split_comment(input: &str) -> (&str, Option<&str>) {
+    match split_outside_labels(input, '#').as_slice() {
+        [expression] => (expression, None),
+        [expression, ..] => (expression, Some(input[expression.len() + 1..].trim())),
+        [] => unreachable!()
+    }
+}
+
+// What a shorthand stands for
+enum Expansion {
+    // Notation to parse in its place, and the label to give it, if any
+    Notation(String, Option<&'static str>),
+    // A roll which the notation cannot write, such as fitd(0)
+    Roll(Roll)
+}
+
+// Matches a shorthand starting at start, giving where it ends and what it stands for.
+// adv and dis are 2d20dl1 and 2d20dh1, as are d20a (or d20adv) and d20d (or d20dis), which
+// may also roll more dice, i.e. 3d20a for elven accuracy. fitd(n) is a Forged in the Dark
+// pool of n d6s keeping the highest, or 2d6 keeping the lowest for 0 dice
+fn expand_shorthand(characters: &[char], start: usize) -> Option<(usize, Expansion)> {
+    if start > 0 && characters[start - 1].is_ascii_alphanumeric() {
+        return None;
+    }
+    let word = |from: usize, word: &str| {
+        let end = from + word.chars().count();
+        let matches = characters.get(from..end).is_some_and(|x| x.iter().copied().eq(word.chars()))
+            && characters.get(end).is_none_or(|x| !x.is_ascii_alphanumeric());
+        if matches { Some(end) } else { None }
+    };
+    if let Some(open) = word(start, "fitd") && characters.get(open) == Some(&'(') {
+        let digits_end = open + 1 + characters[open + 1..].iter().take_while(|x| x.is_ascii_digit()).count();
+        if digits_end == open + 1 || characters.get(digits_end) != Some(&')') {
+            return None;
+        }
+        let given: String = characters[open + 1..digits_end].iter().collect();
+        let pool = match given.parse::<usize>().ok()? {
+            0 => Expansion::Roll(Roll::zero_dice()),
+            x => Expansion::Notation(format!("{}d6kh1", x), None)
+        };
+        return Some((digits_end + 1, pool));
+    }
+    let digits_end = start + characters[start..].iter().take_while(|x| x.is_ascii_digit()).count();
+    let num_rolls: usize;
+    let suffix_start: usize;
+    if digits_end == start && (word(start, "adv").is_some() || word(start, "dis").is_some()) {
+        num_rolls = 2;
+        suffix_start = start;
+    }
+    else if characters.get(digits_end..digits_end + 3).is_some_and(|x| x == ['d', '2', '0']) {
+        let given: String = characters[start..digits_end].iter().collect();
+        num_rolls = given.parse::<usize>().unwrap_or(1).max(2);
+        suffix_start = digits_end + 3;
+    }
+    else {
+        return None;
+    }
+    for (suffix, drop, label) in [("adv", "dl", "advantage"), ("a", "dl", "advantage"), ("dis", "dh", "disadvantage"), ("d", "dh", "disadvantage")] {
+        // The bare words stand alone, without a shorter form
+        if suffix_start == start && suffix.len() == 1 {
+            continue;
+        }
+        if let Some(end) = word(suffix_start, suffix) {
+            return Some((end, Expansion::Notation(format!("{}d20{}{}", num_rolls, drop, num_rolls - 1), Some(label))));
+        }
+    }
+    None
+}
+
 impl ASTInput {
-    pub fn from_string(input: String, help_message: &String) -> Result<Vec<ASTInput>, Box<dyn std::error::Error>> {
-        let mut result: Vec<ASTInput> = input.chars().filter(|x| !x.is_whitespace()).map(|x| ASTInput::Character(x)).collect();
-        ASTInput::from_partial_input(&mut result, help_message)?;
+    pub fn from_string(input: String, help_message: &String, limits: &Limits) -> Result<Vec<ASTInput>, Error> {
+        if input.chars().count() > limits.max_expression_length {
+            return Err(LimitError::ExpressionLength(limits.max_expression_length).into());
+        }
+        // Whitespace is meaningless, apart from inside labels and between two numbers
+        let characters: Vec<char> = input.chars().collect();
+        let mut result: Vec<ASTInput> = Vec::new();
+        let mut position = 0;
+        while position < characters.len() {
+            let character = characters[position];
+            if let Some((end, expansion)) = expand_shorthand(&characters, position) {
+                match expansion {
+                    Expansion::Notation(notation, label) => {
+                        result.extend(notation.chars().map(|x| ASTInput::Character(x, position)));
+                        // Unless the user has given a label of their own
+                        if let Some(x) = label && characters[end..].iter().find(|x| !x.is_whitespace()) != Some(&'[') {
+                            result.push(ASTInput::Label(x.to_owned(), position));
+                        }
+                    }
+                    Expansion::Roll(roll) => {
+                        result.push(ASTInput::PartialAST(AST::new(ASType::RollOrConstant(RollOrConstant::Roll(roll)), false)));
+                    }
+                }
+                position = end;
+                continue;
+            }
+            if character == '[' {
+                let Some(length) = characters[position..].iter().position(|x| *x == ']') else {
+                    return Err(ParseError::new("Mismatched label brackets", Some(position)).into());
+                };
+                let label: String = characters[position + 1..position + length].iter().collect();
+                let label = label.trim();
+                if label.is_empty() {
+                    return Err(ParseError::new("Expected a label inside brackets", Some(position)).into());
+                }
+                // A label set apart at the very end, as in d20 + 5 [attack], names the whole
+                // expression, so bracket everything before it. Not when other parts are
+                // labelled though, as in 2d6[fire] + 1d8 [slashing], where it would put one
+                // damage type inside another
+                let set_apart = position > 0 && characters[position - 1].is_whitespace();
+                let labelled_within = result.iter().any(|x| matches!(x, ASTInput::Label(..)));
+                if set_apart && !labelled_within && !result.is_empty() && characters[position + length + 1..].iter().all(|x| x.is_whitespace()) {
+                    result.insert(0, ASTInput::Character('(', position));
+                    result.push(ASTInput::Character(')', position));
+                }
+                result.push(ASTInput::Label(label.to_owned(), position));
+                position += length;
+            }
+            else if character == ']' {
+                return Err(ParseError::new("Mismatched label brackets", Some(position)).into());
+            }
+            else if !character.is_whitespace() {
+                // Whitespace between two numbers, as in 5 2, would otherwise join them into 52
+                let after_number = characters[..position].iter().rev().find(|x| !x.is_whitespace()).is_some_and(char::is_ascii_digit);
+                if after_number && character.is_ascii_digit() && characters[position - 1].is_whitespace() {
+                    return Err(ParseError::new("Expected an operator between two numbers", Some(position)).into());
+                }
+                result.push(ASTInput::Character(character, position));
+            }
+            position += 1;
+        }
+        ASTInput::from_partial_input(&mut result, help_message, limits, 0)?;
         Ok(result)
     }
 
-    fn from_partial_input(input: &mut Vec<ASTInput>, help_message: &String) -> Result<(), Box<dyn std::error::Error>>{
+    // depth is the number of brackets enclosing this input
+    fn from_partial_input(input: &mut Vec<ASTInput>, help_message: &String, limits: &Limits, depth: usize) -> Result<(), Error>{
+        if depth > limits.max_nesting_depth {
+            return Err(LimitError::NestingDepth(limits.max_nesting_depth).into());
+        }
         let mut result_idx = 0;
         while result_idx < input.len() {
             let result_char = &input[result_idx];
             result_idx += 1;
             match result_char {
-                ASTInput::Character(character) => {
+                ASTInput::Character(character, position) => {
+                    let position = *position;
                     if *character == '(' {
                         // Handle brackets
                         let mut num_brackets: usize = 1;
                         let mut bracket_idx = result_idx;
                         while num_brackets > 0 {
                             if bracket_idx == input.len() {
-                                return Err("Mismatched brackets".into());
+                                return Err(ParseError::new("Mismatched brackets", Some(position)).into());
                             }
-                            if input[bracket_idx] == ASTInput::Character(')') {
+                            if let ASTInput::Character(')', _) = input[bracket_idx] {
                                 num_brackets -= 1;
                             }
-                            else if input[bracket_idx] == ASTInput::Character('(') {
+                            else if let ASTInput::Character('(', _) = input[bracket_idx] {
                                 num_brackets += 1;
                             }
                             bracket_idx += 1;
@@ -50,22 +219,59 @@ impl ASTInput {
                         // Remove the brackets
                         input.remove(bracket_idx - 1);
                         input.remove(result_idx - 1);
-                        let mut bracketed_input = input.drain((result_idx - 1)..(bracket_idx - 2)).collect();
-                        ASTInput::from_partial_input(&mut bracketed_input, help_message)?;
-                        let handled_bracket = ASType::consume_input(&mut bracketed_input)?;
-                        input.insert(result_idx - 1, ASTInput::PartialAST(AST::new(handled_bracket, true)));
+                        let mut bracketed_input: Vec<ASTInput> = input.drain((result_idx - 1)..(bracket_idx - 2)).collect();
+                        if bracketed_input.is_empty() {
+                            return Err(ParseError::new("Expected an expression inside brackets", Some(position)).into());
+                        }
+                        ASTInput::from_partial_input(&mut bracketed_input, help_message, limits, depth + 1)?;
+                        let mut handled_bracket = AST::consume_input(&mut bracketed_input)?;
+                        handled_bracket.is_bracketed = true;
+                        // Letters just before the brackets name a function, as in crit(2d6 + 3)
+                        let name_start = input[..result_idx - 1].iter().rposition(|x| !matches!(x, ASTInput::Character(x, _) if x.is_ascii_alphabetic())).map_or(0, |x| x + 1);
+                        if name_start < result_idx - 1 {
+                            let name: String = input.drain(name_start..(result_idx - 1)).map(|x| match x {
+                                ASTInput::Character(x, _) => x,
+                                _ => unreachable!()
+                            }).collect();
+                            let Some(rule) = CritRule::from_name(&name) else {
+                                return Err(ParseError::new(format!("Unknown function: {}", name), Some(position - name.chars().count())).into());
+                            };
+                            let labelled = handled_bracket.label.is_some();
+                            handled_bracket = handled_bracket.crit(rule);
+                            result_idx = name_start + 1;
+                            // A label of the user's own after the brackets, such as the damage
+                            // type in crit(2d6)[fire], takes the place of crit
+                            if !labelled && matches!(input.get(result_idx - 1), Some(ASTInput::Label(..))) {
+                                handled_bracket.label = None;
+                            }
+                        }
+                        input.insert(result_idx - 1, ASTInput::PartialAST(handled_bracket));
                     }
-                    else if character.is_digit(10) || *character == 'd' {
+                    else if character.is_ascii_digit() || *character == 'd' {
                         let idx_before_roll = result_idx - 1;
                         result_idx -= 1;
                         let roll = consume_input_to_roll(input, &mut result_idx, help_message)?;
-                        input[idx_before_roll] = ASTInput::PartialAST(AST::new(ASType::RollOrConstant(roll), false));
-                        if idx_before_roll + 1 < input.len() {
-                            input.drain((idx_before_roll + 1)..(result_idx - 1));
+                        if let RollOrConstant::Roll(x) = &roll && x.num_rolls > limits.max_dice_per_roll {
+                            return Err(LimitError::DicePerRoll(limits.max_dice_per_roll).into());
                         }
+                        input[idx_before_roll] = ASTInput::PartialAST(AST::new(ASType::RollOrConstant(roll), false));
+                        input.drain((idx_before_roll + 1)..result_idx);
                         result_idx = idx_before_roll;
                     }
                 }
+                ASTInput::Label(_, position) => {
+                    let position = *position;
+                    // A label names whatever came just before it, which has been parsed by now
+                    let ASTInput::Label(label, _) = input.remove(result_idx - 1) else {unreachable!()};
+                    result_idx -= 1;
+                    let Some(ASTInput::PartialAST(labelled)) = result_idx.checked_sub(1).map(|x| &mut input[x]) else {
+                        return Err(ParseError::new("A label must follow the part of the expression it names", Some(position)).into());
+                    };
+                    if labelled.label.is_some() {
+                        return Err(ParseError::new("This part of the expression is already labelled", Some(position)).into());
+                    }
+                    labelled.label = Some(label);
+                }
                 ASTInput::PartialAST(_x) => {
                 }
             }
@@ -74,150 +280,380 @@ impl ASTInput {
     }
 }
 
-// BEDMAS
-const OPERATOR_CHARS_ORDERED: &[char] = &['^', '/', '*', '+', '-'];
+/// The house rules for how a critical hit adds to damage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CritRule {
+    /// Roll every die twice, as in 5e, written `crit(...)`
+    DoubleDice,
+    /// Add the most the dice could roll to what they do roll, written `critmax(...)`
+    MaxDice,
+    /// Double the whole total, modifiers included, written `crittotal(...)`
+    DoubleTotal
+}
 
-#[derive(Clone, PartialEq, Debug)]
+impl CritRule {
+    // Function names may not contain a d, which would begin a roll
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "crit" => Some(Self::DoubleDice),
+            "critmax" => Some(Self::MaxDice),
+            "crittotal" => Some(Self::DoubleTotal),
+            _ => None
+        }
+    }
+}
+
+// BEDMAS, where operators sharing a level are applied left to right
+// (apart from exponentiation, so that 2^3^2 is 2^(3^2))
+const OPERATOR_CHARS_ORDERED: &[&[char]] = &[&['^'], &['*', '/'], &['+', '-']];
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AST {
     ast_type: ASType,
-    is_bracketed: bool
+    // Only remembered so that the breakdown can echo the user's brackets back to them,
+    // and so does not count towards equality
+    is_bracketed: bool,
+    // The name given to this part of the expression, i.e. fire in 2d6[fire]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    label: Option<String>
+}
+
+impl PartialEq for AST {
+    fn eq(&self, other: &Self) -> bool {
+        self.ast_type == other.ast_type && self.label == other.label
+    }
+}
+
+// Prints the expression in its canonical form, with only the brackets which are needed
+impl fmt::Display for AST {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.label {
+            // A label binds more tightly than any operator, so it needs brackets around an operation
+            Some(label) if self.ast_type.operation().is_some() => write!(f, "({})[{}]", self.ast_type, label),
+            Some(label) => write!(f, "{}[{}]", self.ast_type, label),
+            None => write!(f, "{}", self.ast_type)
+        }
+    }
 }
 
 impl AST {
     fn new(ast_type: ASType, is_bracketed: bool) -> Self {
-        Self { ast_type, is_bracketed }
+        Self { ast_type, is_bracketed, label: None }
     }
 
-    pub fn compute_with_output(&mut self, output: &mut String, rng: &mut ThreadRng, skip_dropped: bool, colour: bool) -> Result<i64, Box<dyn std::error::Error>> {
-        if self.is_bracketed {
-            *output += "(";
-        }
-        let result = self.ast_type.compute_with_output(output, rng, skip_dropped, colour)?;
-        if self.is_bracketed {
-            *output += ")";
+    /// The label given to this part of the expression, i.e. `fire` in `2d6[fire]`.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The damage of this expression on a critical hit, labelled `crit`. The dice count
+    /// towards the limits as usual, but the new tree is not checked against them.
+    pub fn crit(&self, rule: CritRule) -> Self {
+        let mut result = match rule {
+            CritRule::DoubleDice | CritRule::MaxDice => self.crit_dice(rule),
+            CritRule::DoubleTotal => {
+                let two = AST::new(ASType::RollOrConstant(RollOrConstant::Const(ConstantRoll { constant_result: 2 })), false);
+                // Doubling all of it, i.e. (2d6 + 4) * 2 rather than 2d6 + 4 * 2
+                let mut doubled = self.clone();
+                doubled.is_bracketed = !matches!(doubled.ast_type, ASType::RollOrConstant(_));
+                AST::new(ASType::Multiply(Box::new(doubled), Box::new(two)), false)
+            }
+        };
+        result.is_bracketed = self.is_bracketed;
+        // Keeping any label of its own, i.e. a damage type
+        result.label = self.label.clone().or(Some("crit".to_owned()));
+        result
+    }
+
+    // Applies a crit to every roll in this tree, leaving the rest as it is
+    fn crit_dice(&self, rule: CritRule) -> Self {
+        let ast_type = match &self.ast_type {
+            ASType::Add(x, y) => ASType::Add(Box::new(x.crit_dice(rule)), Box::new(y.crit_dice(rule))),
+            ASType::Subtract(x, y) => ASType::Subtract(Box::new(x.crit_dice(rule)), Box::new(y.crit_dice(rule))),
+            ASType::Multiply(x, y) => ASType::Multiply(Box::new(x.crit_dice(rule)), Box::new(y.crit_dice(rule))),
+            ASType::Divide(x, y) => ASType::Divide(Box::new(x.crit_dice(rule)), Box::new(y.crit_dice(rule))),
+            ASType::Power(x, y) => ASType::Power(Box::new(x.crit_dice(rule)), Box::new(y.crit_dice(rule))),
+            ASType::RollOrConstant(RollOrConstant::Roll(x)) if rule == CritRule::DoubleDice => {
+                // Any dice dropped or kept are doubled along with the rest
+                let double = |x: usize| x.saturating_mul(2);
+                let drop_die = x.drop_die.as_ref().map(|drop_die| match drop_die {
+                    DropDie::DropLowest(n) => DropDie::DropLowest(double(*n)),
+                    DropDie::DropHighest(n) => DropDie::DropHighest(double(*n)),
+                    DropDie::KeepHighest(n) => DropDie::KeepHighest(double(*n)),
+                    DropDie::KeepLowest(n) => DropDie::KeepLowest(double(*n))
+                });
+                // Twice as many dice are no longer a pool of zero
+                ASType::RollOrConstant(RollOrConstant::Roll(Roll { num_rolls: double(x.num_rolls), drop_die, zero_dice: false, ..x.clone() }))
+            }
+            ASType::RollOrConstant(RollOrConstant::Roll(x)) => {
+                let (drop_n, _) = x.dropped();
+                let most = (x.num_rolls - drop_n) as i64 * i64::from(x.dice_sides);
+                let most = AST::new(ASType::RollOrConstant(RollOrConstant::Const(ConstantRoll { constant_result: most })), false);
+                let roll = AST::new(self.ast_type.clone(), false);
+                // Bracketed, so that the breakdown keeps the two together
+                let sum = ASType::Add(Box::new(most), Box::new(roll));
+                return Self { ast_type: sum, is_bracketed: true, label: self.label.clone() };
+            }
+            ASType::RollOrConstant(RollOrConstant::Const(_)) => self.ast_type.clone()
+        };
+        Self { ast_type, is_bracketed: self.is_bracketed, label: self.label.clone() }
+    }
+
+    // How tightly this node binds, for deciding where brackets are needed when printing
+    fn precedence(&self) -> u8 {
+        if self.label.is_some() {
+            return u8::MAX;
         }
-        Ok(result)
+        self.ast_type.precedence()
     }
 
-    pub fn compute(&mut self, rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>> {
+    pub fn compute_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error> {
+        self.compute_with_modifiers(rng, &[])
+    }
+
+    // As compute_with_breakdown, applying the modifiers to each labelled part they name as it
+    // is evaluated, so that whatever the part is added to, subtracted from or multiplied by
+    // sees the modified total
+    pub(crate) fn compute_with_modifiers<R: Rng + ?Sized>(&self, rng: &mut R, modifiers: &[DamageModifier]) -> Result<Breakdown, Error> {
+        let within = modifiers_within(modifiers, self.label.as_deref());
+        let mut breakdown = self.ast_type.compute_with_modifiers(rng, &within)?;
+        breakdown.is_bracketed = self.is_bracketed;
+        breakdown.label = self.label.clone();
+        Ok(apply_damage_modifiers(breakdown, modifiers)?)
+    }
+
+    pub fn compute<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error> {
         self.ast_type.compute(rng)
     }
 
-    pub fn consume_input(input: &mut Vec<ASTInput>) -> Result<Self, Box<dyn std::error::Error>> {
-        let ast_type = ASType::consume_input(input)?;
-        Ok(Self::new(ast_type, false))
+    pub fn distribution(&self) -> Result<Distribution, Error> {
+        self.ast_type.distribution()
+    }
+
+    pub fn consume_input(input: &mut Vec<ASTInput>) -> Result<Self, Error> {
+        for operator_chars in OPERATOR_CHARS_ORDERED {
+            let right_to_left = operator_chars.contains(&'^');
+            let mut input_idx: usize = if right_to_left { input.len().saturating_sub(1) } else { 0 };
+            // While loop since the size of input will change here
+            while input_idx < input.len() {
+                let mut handled = false;
+                if let ASTInput::Character(x, _) = &input[input_idx] && operator_chars.contains(x) {
+                    handled = match x {
+                        '^' => handle_operator!(Power, input, input_idx),
+                        '/' => handle_operator!(Divide, input, input_idx),
+                        '*' => handle_operator!(Multiply, input, input_idx),
+                        '+' => handle_operator!(Add, input, input_idx),
+                        '-' => handle_operator!(Subtract, input, input_idx),
+                        _ => false
+                    };
+                }
+                if right_to_left {
+                    // Step back past the operand, which is now the joined tree if handled
+                    if input_idx == 0 {
+                        break;
+                    }
+                    input_idx -= 1;
+                }
+                else if !handled {
+                    // Otherwise the next operator has shifted into input_idx
+                    input_idx += 1;
+                }
+            }
+        }
+        // Everything should now have been joined into a single tree
+        if input.len() == 1 && let ASTInput::PartialAST(_result) = &input[0] {
+            let ASTInput::PartialAST(result) = input.remove(0) else {unreachable!()};
+            return Ok(result);
+        }
+        if input.is_empty() {
+            return Err(ParseError::new("Expected an expression", None).into());
+        }
+        // Point at the first character which could not be joined onto anything
+        let position = input.iter().find_map(|x| match x {
+            ASTInput::Character(_, position) | ASTInput::Label(_, position) => Some(*position),
+            ASTInput::PartialAST(_) => None
+        });
+        Err(ParseError::new("Could not understand the expression", position).into())
+    }
+
+    // The number of dice evaluating this tree will roll, checking each roll against the limits
+    pub(crate) fn count_dice(&self, limits: &Limits) -> Result<usize, LimitError> {
+        self.ast_type.count_dice(limits)
+    }
+
+    // Checks that evaluating this tree will not roll more dice than allowed, before any are rolled
+    pub fn check_limits(&self, limits: &Limits) -> Result<(), LimitError> {
+        let total_dice = self.count_dice(limits)?;
+        if total_dice > limits.max_total_dice {
+            return Err(LimitError::TotalDice(limits.max_total_dice));
+        }
+        Ok(())
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ASType {
     Add(Box<AST>, Box<AST>),
     Subtract(Box<AST>, Box<AST>),
     Multiply(Box<AST>, Box<AST>),
     Divide(Box<AST>, Box<AST>),
     Power(Box<AST>, Box<AST>),
-    RollOrConstant(RollOrConstant),
-    HiddenConstant(i64)
+    RollOrConstant(RollOrConstant)
 }
 
-impl ASType {
-    pub fn compute_with_output(&mut self, output: &mut String, rng: &mut ThreadRng, skip_dropped: bool, colour: bool) -> Result<i64, Box<dyn std::error::Error>> {
-        let mut result: i64 = 0;
+fn checked_divide(x: i64, y: i64) -> Result<i64, ArithmeticError> {
+    if y == 0 {
+        return Err(ArithmeticError::DivisionByZero);
+    }
+    // i64::MIN / -1 is the only other way for division to fail
+    x.checked_div(y).ok_or(ArithmeticError::Overflow)
+}
+
+fn checked_power(base: i64, exponent: i64) -> Result<i64, ArithmeticError> {
+    if exponent < 0 {
+        return Err(ArithmeticError::NegativeExponent);
+    }
+    match u32::try_from(exponent) {
+        Ok(exponent) => base.checked_pow(exponent).ok_or(ArithmeticError::Overflow),
+        // Only a handful of bases survive such a large exponent
+        Err(_) => match base {
+            0 | 1 => Ok(base),
+            -1 => Ok(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => Err(ArithmeticError::Overflow)
+        }
+    }
+}
+
+impl Operator {
+    pub fn apply(&self, x: i64, y: i64) -> Result<i64, ArithmeticError> {
         match self {
-            Self::Add(x, y) => {
-                let x_result = x.compute_with_output(output, rng, skip_dropped, colour)?;
-                *output += " + ";
-                result += x_result + y.compute_with_output(output, rng, skip_dropped, colour)?;
-            }
-            Self::Subtract(x, y) => {
-                let x_result = x.compute_with_output(output, rng, skip_dropped, colour)?;
-                *output += " - ";
-                result += x_result - y.compute_with_output(output, rng, skip_dropped, colour)?;
-            }
-            Self::Multiply(x, y) => {
-                let x_result = x.compute_with_output(output, rng, skip_dropped, colour)?;
-                *output += " * ";
-                result += x_result * y.compute_with_output(output, rng, skip_dropped, colour)?;
-            }
-            Self::Divide(x, y) => {
-                let x_result = x.compute_with_output(output, rng, skip_dropped, colour)?;
-                *output += " / ";
-                result += x_result / y.compute_with_output(output, rng, skip_dropped, colour)?;
-            },
-            Self::Power(x, y) => {
-                let x_result = x.compute_with_output(output, rng, skip_dropped, colour)?;
-                *output += " ^ "; result += x_result.pow(y.compute_with_output(output, rng, skip_dropped, colour)?.try_into()?);
-            }
-            Self::RollOrConstant(x) => {
-                result += x.roll_with_output(output, rng, skip_dropped, colour)?;
+            Self::Add => x.checked_add(y).ok_or(ArithmeticError::Overflow),
+            Self::Subtract => x.checked_sub(y).ok_or(ArithmeticError::Overflow),
+            Self::Multiply => x.checked_mul(y).ok_or(ArithmeticError::Overflow),
+            Self::Divide => checked_divide(x, y),
+            Self::Power => checked_power(x, y)
+        }
+    }
+}
+
+impl fmt::Display for ASType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operation() {
+            Some((operator, x, y)) => {
+                // An operand needs brackets if it binds more loosely than this operator, or
+                // equally tightly but on the side the operator does not group from
+                let x_bracketed = x.precedence() < operator.precedence()
+                    || (x.precedence() == operator.precedence() && operator.is_right_associative());
+                let y_bracketed = y.precedence() < operator.precedence()
+                    || (y.precedence() == operator.precedence() && !operator.is_right_associative());
+                if x_bracketed {
+                    write!(f, "({})", x)?;
+                }
+                else {
+                    write!(f, "{}", x)?;
+                }
+                write!(f, " {} ", operator.symbol())?;
+                if y_bracketed {
+                    write!(f, "({})", y)
+                }
+                else {
+                    write!(f, "{}", y)
+                }
             }
-            Self::HiddenConstant(x) => {
-                result += *x;
+            None => {
+                let Self::RollOrConstant(x) = self else {unreachable!()};
+                write!(f, "{}", x)
             }
         }
-        Ok(result)
+    }
+}
+
+impl ASType {
+    // How tightly this node binds, for deciding where brackets are needed when printing
+    fn precedence(&self) -> u8 {
+        match self.operation() {
+            Some((operator, _, _)) => operator.precedence(),
+            None => u8::MAX
+        }
     }
 
-    pub fn compute(&mut self, rng: &mut ThreadRng) -> Result<i64, Box<dyn std::error::Error>> {
-        let mut result: i64 = 0;
+    // The number of dice rolled by this tree, checking each roll against the limits on the way
+    fn count_dice(&self, limits: &Limits) -> Result<usize, LimitError> {
         match self {
-            Self::Add(x, y) => {
-                let x_result = x.compute(rng)?;
-                result += x_result + y.compute(rng)?;
+            Self::Add(x, y) | Self::Subtract(x, y) | Self::Multiply(x, y) | Self::Divide(x, y) | Self::Power(x, y) => {
+                Ok(x.ast_type.count_dice(limits)?.saturating_add(y.ast_type.count_dice(limits)?))
             }
-            Self::Subtract(x, y) => {
-                let x_result = x.compute(rng)?;
-                result += x_result - y.compute(rng)?;
+            Self::RollOrConstant(RollOrConstant::Roll(x)) => {
+                if x.num_rolls > limits.max_dice_per_roll {
+                    return Err(LimitError::DicePerRoll(limits.max_dice_per_roll));
+                }
+                Ok(x.num_rolls)
             }
-            Self::Multiply(x, y) => {
-                let x_result = x.compute(rng)?;
-                result += x_result * y.compute(rng)?;
+            Self::RollOrConstant(RollOrConstant::Const(_)) => Ok(0)
+        }
+    }
+
+    // The operator joining the two sides of this node, if it has two sides
+    fn operation(&self) -> Option<(Operator, &AST, &AST)> {
+        match self {
+            Self::Add(x, y) => Some((Operator::Add, x, y)),
+            Self::Subtract(x, y) => Some((Operator::Subtract, x, y)),
+            Self::Multiply(x, y) => Some((Operator::Multiply, x, y)),
+            Self::Divide(x, y) => Some((Operator::Divide, x, y)),
+            Self::Power(x, y) => Some((Operator::Power, x, y)),
+            Self::RollOrConstant(_) => None
+        }
+    }
+
+    pub fn compute_with_breakdown<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<Breakdown, Error> {
+        self.compute_with_modifiers(rng, &[])
+    }
+
+    fn compute_with_modifiers<R: Rng + ?Sized>(&self, rng: &mut R, modifiers: &[DamageModifier]) -> Result<Breakdown, Error> {
+        match self.operation() {
+            Some((operator, x, y)) => {
+                let x_result = x.compute_with_modifiers(rng, modifiers)?;
+                let y_result = y.compute_with_modifiers(rng, modifiers)?;
+                let total = operator.apply(x_result.total, y_result.total)?;
+                Ok(Breakdown::new(total, BreakdownKind::Operation(operator, Box::new(x_result), Box::new(y_result))))
             }
-            Self::Divide(x, y) => {
-                let x_result = x.compute(rng)?;
-                result += x_result / y.compute(rng)?;
-            },
-            Self::Power(x, y) => {
-                let x_result = x.compute(rng)?;
-                result += x_result.pow(y.compute(rng)?.try_into()?);
+            None => {
+                let Self::RollOrConstant(x) = self else {unreachable!()};
+                x.roll_with_breakdown(rng)
             }
-            Self::RollOrConstant(x) => {
-                result += x.roll(rng)?;
+        }
+    }
+
+    pub fn distribution(&self) -> Result<Distribution, Error> {
+        match self.operation() {
+            Some((operator, x, y)) => {
+                x.distribution()?.combine(&y.distribution()?, |x, y| operator.apply(x, y))
             }
-            Self::HiddenConstant(x) => {
-                result += *x;
+            None => {
+                let Self::RollOrConstant(x) = self else {unreachable!()};
+                x.distribution()
             }
         }
-        Ok(result)
     }
 
-    pub fn consume_input(input: &mut Vec<ASTInput>) -> Result<Self, Box<dyn std::error::Error>> {
-        for operator_char in OPERATOR_CHARS_ORDERED {
-            let mut input_idx: usize = 0;
-            // While loop since the size of input will change here
-            while input_idx < input.len() {
-                let input_singlet = &input[input_idx];
-                match input_singlet {
-                    ASTInput::Character(x) => {
-                        if x == operator_char {
-                            match x {
-                                '^' => handle_operator!(Power, input, input_idx),
-                                '/' => handle_operator!(Divide, input, input_idx),
-                                '*' => handle_operator!(Multiply, input, input_idx),
-                                '+' => handle_operator!(Add, input, input_idx),
-                                '-' => handle_operator!(Subtract, input, input_idx),
-                                _ => {}
-                            }
-                        }
-                    }
-                    ASTInput::PartialAST(_x) => {}
-                }
-                input_idx += 1;
+    pub fn compute<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, Error> {
+        match self.operation() {
+            Some((operator, x, y)) => {
+                let x_result = x.compute(rng)?;
+                Ok(operator.apply(x_result, y.compute(rng)?)?)
+            }
+            None => {
+                let Self::RollOrConstant(x) = self else {unreachable!()};
+                x.roll(rng)
             }
         }
-        if let ASTInput::PartialAST(result) = input.remove(0) {
-            return Ok(result.ast_type);
-        }
-        return Err("IUNNO".into());
+    }
+
+    // Any label on the tree as a whole is lost, so AST::consume_input is usually wanted instead
+    pub fn consume_input(input: &mut Vec<ASTInput>) -> Result<Self, Error> {
+        Ok(AST::consume_input(input)?.ast_type)
     }
 }
@@ -0,0 +1,106 @@
+use js_sys::{Array, Float64Array, Object, Reflect};
+use rand::{rngs::StdRng, SeedableRng};
+use wasm_bindgen::prelude::*;
+use crate::error::Error;
+use crate::expression::Expression;
+
+// Setting a property on an object we have just created cannot fail
+fn set(object: &Object, key: &str, value: impl Into<JsValue>) {
+    Reflect::set(object, &JsValue::from_str(key), &value.into()).unwrap_throw();
+}
+
+// Turns an error into a JS Error named after its kind (ParseError, LimitError or
+// ArithmeticError), so that callers can tell them apart. Parse errors also carry the
+// position of the problem, or undefined if it is not known
+fn to_js_error(error: Error) -> JsValue {
+    let js_error = js_sys::Error::new(&error.to_string());
+    match &error {
+        Error::Parse(x) => {
+            js_error.set_name("ParseError");
+            js_error.set_message(&x.message);
+            set(&js_error, "position", x.position.map(|x| x as u32));
+        }
+        Error::Limit(_) => js_error.set_name("LimitError"),
+        Error::Arithmetic(_) => js_error.set_name("ArithmeticError"),
+        _ => {}
+    }
+    js_error.into()
+}
+
+// A parsed expression, which can be rolled any number of times from JS
+#[wasm_bindgen(js_name = Expression)]
+pub struct JsExpression {
+    expression: Expression
+}
+
+#[wasm_bindgen(js_class = Expression)]
+impl JsExpression {
+    // Throws if the input is not a valid expression
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: &str) -> Result<JsExpression, JsValue> {
+        Ok(JsExpression { expression: Expression::parse(input).map_err(to_js_error)? })
+    }
+
+    // Rolls the expression, giving the same dice every time for the same seed.
+    // Returns {total, expression, text, dice: [{face, sides, dropped, crit, fumble}], labels,
+    // damage}, where labels maps each label in the expression to its total as rolled, and
+    // damage maps each resisted, vulnerable or immune damage type to its total afterwards
+    pub fn roll(&self, seed: Option<u32>) -> Result<Object, JsValue> {
+        let breakdown = match seed {
+            Some(x) => self.expression.roll_with_breakdown(&mut StdRng::seed_from_u64(x.into())),
+            None => self.expression.roll_with_breakdown(&mut rand::rng())
+        }.map_err(to_js_error)?;
+        let dice = Array::new();
+        for die in breakdown.dice() {
+            let js_die = Object::new();
+            set(&js_die, "face", die.face);
+            set(&js_die, "sides", die.sides);
+            set(&js_die, "dropped", die.dropped);
+            set(&js_die, "crit", die.crit);
+            set(&js_die, "fumble", die.fumble);
+            dice.push(&js_die);
+        }
+        let labels = Object::new();
+        for (label, total) in breakdown.label_totals().map_err(|x| to_js_error(x.into()))? {
+            set(&labels, &label, total as f64);
+        }
+        let damage = Object::new();
+        for damage_type in breakdown.damage_types().map_err(|x| to_js_error(x.into()))? {
+            set(&damage, &damage_type.damage_type, damage_type.total as f64);
+        }
+        let mut text = String::new();
+        breakdown.render(&mut text, false, None, false);
+        let result = Object::new();
+        // Totals are i64, but any realistic total fits exactly in a JS number
+        set(&result, "total", breakdown.total as f64);
+        set(&result, "expression", self.expression.to_string());
+        set(&result, "text", text);
+        set(&result, "dice", dice);
+        set(&result, "labels", labels);
+        set(&result, "damage", damage);
+        Ok(result)
+    }
+
+    // The exact distribution of the expression's total, as
+    // {min, max, mean, variance, standardDeviation, totals, probabilities}, where totals
+    // and probabilities are parallel Float64Arrays holding every possible total in order
+    pub fn distribution(&self) -> Result<Object, JsValue> {
+        let distribution = self.expression.distribution().map_err(to_js_error)?;
+        let (totals, probabilities): (Vec<f64>, Vec<f64>) = distribution.iter().map(|(total, p)| (total as f64, p)).unzip();
+        let result = Object::new();
+        set(&result, "min", distribution.min() as f64);
+        set(&result, "max", distribution.max() as f64);
+        set(&result, "mean", distribution.mean());
+        set(&result, "variance", distribution.variance());
+        set(&result, "standardDeviation", distribution.standard_deviation());
+        set(&result, "totals", Float64Array::from(&totals[..]));
+        set(&result, "probabilities", Float64Array::from(&probabilities[..]));
+        Ok(result)
+    }
+
+    // The expression in its canonical form
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        self.expression.to_string()
+    }
+}
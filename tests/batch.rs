@@ -0,0 +1,30 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Runs the binary in batch mode on the given standard input
+fn roll_batch(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_roll"))
+        .args(["--color", "never", "--short-output", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn blank_and_comment_lines_are_skipped() {
+    let output = roll_batch("# just a note\n\n  # indented\n2 + 3\n4 # four\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5\n4 # four\n");
+}
+
+#[test]
+fn bad_lines_are_reported_without_stopping_the_rest() {
+    let output = roll_batch("2 +\n# note\n7\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "7\n");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error on line 1:"));
+}